@@ -0,0 +1,17 @@
+//! Decides whether lock hierarchies are checked and tells the crate via `cfg(lock_check)`.
+//!
+//! By default checks are performed in builds with debug assertions. The `always-check` feature
+//! enables them in any build, the `never-check` feature disables them. If both features are
+//! enabled (e.g. by `--all-features`), `always-check` takes precedence.
+
+use std::env;
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(lock_check)");
+    let always = env::var_os("CARGO_FEATURE_ALWAYS_CHECK").is_some();
+    let never = env::var_os("CARGO_FEATURE_NEVER_CHECK").is_some();
+    let debug_assertions = env::var_os("CARGO_CFG_DEBUG_ASSERTIONS").is_some();
+    if always || (debug_assertions && !never) {
+        println!("cargo::rustc-cfg=lock_check");
+    }
+}
@@ -0,0 +1,282 @@
+use std::marker::PhantomData;
+
+#[cfg(lock_check)]
+use crate::AnyLevel;
+use crate::LockLevel;
+#[cfg(lock_check)]
+use std::{cell::RefCell, panic::Location, thread_local};
+
+#[cfg(lock_check)]
+use crate::{
+    level::{held_locks, Level},
+    violation::{report, HierarchyViolation},
+    LockInfo,
+};
+
+#[cfg(lock_check)]
+thread_local! {
+    /// Blocking sections the thread is currently in. Their level is the threshold, i.e. only locks
+    /// with lower levels may be held within them.
+    static BLOCKING_SECTIONS: RefCell<Vec<LockInfo>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Reports a violation in debug builds if the current thread holds any lock. Call this before
+/// blocking operations, e.g. I/O or waiting for a remote call, which must not stall other threads
+/// waiting for a lock.
+///
+/// ```
+/// use lock_hierarchy::{assert_no_locks_held, Mutex};
+///
+/// let mutex = Mutex::new(());
+/// drop(mutex.lock().unwrap());
+/// // Fine, the lock has already been released
+/// assert_no_locks_held();
+/// ```
+#[track_caller]
+pub fn assert_no_locks_held() {
+    #[cfg(lock_check)]
+    if let Some(violation) = entry_violation(&section_info(AnyLevel::BOTTOM)) {
+        report(violation)
+    }
+}
+
+/// Marks a section of blocking work, e.g. I/O or waiting for a remote call, which must not stall
+/// other threads waiting for a lock.
+///
+/// In debug builds a violation is reported if the section is entered while the current thread
+/// holds any lock, or if a lock is acquired within the section. A section created with
+/// [`BlockingSection::with_level`] only allows locks with lower levels of the same type, see
+/// [`LockLevel`].
+///
+/// ```
+/// use lock_hierarchy::{BlockingSection, Mutex};
+///
+/// let metrics = Mutex::with_level(0u32, 0);
+/// let database = Mutex::with_level((), 1);
+///
+/// let _guard = metrics.lock().unwrap();
+/// // Fine, only locks with levels lower than 1 are held
+/// let _section = BlockingSection::with_level(1);
+/// // Would panic, level 1 must not be held while blocking
+/// // let _guard = database.lock().unwrap();
+/// # let _ = database;
+/// ```
+#[must_use = "the blocking section ends once it is dropped"]
+#[derive(Debug)]
+pub struct BlockingSection {
+    /// The threshold. [`AnyLevel::BOTTOM`] if no locks may be held at all.
+    #[cfg(lock_check)]
+    level: AnyLevel,
+    /// Sections are tracked per thread, so they must be left on the thread which entered them.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl BlockingSection {
+    /// Enters a section in which no lock may be held.
+    #[track_caller]
+    pub fn new() -> Self {
+        #[cfg(lock_check)]
+        {
+            Self::enter(AnyLevel::BOTTOM)
+        }
+        #[cfg(not(lock_check))]
+        Self::with_level(0)
+    }
+
+    /// Enters a section in which only locks with a level lower than `level` may be held.
+    #[track_caller]
+    pub fn with_level(level: impl LockLevel) -> Self {
+        #[cfg(lock_check)]
+        {
+            Self::enter(AnyLevel::new(level))
+        }
+        #[cfg(not(lock_check))]
+        {
+            let _ = level;
+            BlockingSection {
+                _not_send: PhantomData,
+            }
+        }
+    }
+
+    #[cfg(lock_check)]
+    #[track_caller]
+    fn enter(level: AnyLevel) -> Self {
+        let section = section_info(level.clone());
+        if let Some(violation) = entry_violation(&section) {
+            report(violation)
+        }
+        // The violation handler may choose to continue, so we track the section either way
+        BLOCKING_SECTIONS.with(|sections| sections.borrow_mut().push(section));
+        BlockingSection {
+            level,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// Describes a blocking section entered by the caller, with `level` as threshold.
+#[cfg(lock_check)]
+#[track_caller]
+fn section_info(level: AnyLevel) -> LockInfo {
+    let mut section = Level::new(0).info(Location::caller());
+    section.level = level;
+    section
+}
+
+impl Default for BlockingSection {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(lock_check)]
+impl Drop for BlockingSection {
+    fn drop(&mut self) {
+        BLOCKING_SECTIONS.with(|sections| {
+            let mut sections = sections.borrow_mut();
+            let index = sections
+                .iter()
+                .rposition(|section| *section.level() == self.level)
+                .expect("Position must exist, because we inserted it on entry!");
+            sections.remove(index);
+        });
+    }
+}
+
+/// The violation entering `section` would cause, if any.
+#[cfg(lock_check)]
+fn entry_violation(section: &LockInfo) -> Option<HierarchyViolation> {
+    let held = held_locks();
+    held.iter()
+        .any(|held| !held.level().is_lower_than(section.level()))
+        .then(|| HierarchyViolation::held_while_blocking(section.clone(), None, held))
+}
+
+/// The violation acquiring a lock with `level` would cause, because the thread is in a blocking
+/// section. Levels of a different type than the section do not count as lower.
+#[cfg(lock_check)]
+pub(crate) fn acquisition_violation(
+    level: &AnyLevel,
+    attempted: impl FnOnce() -> LockInfo,
+) -> Option<HierarchyViolation> {
+    let section = BLOCKING_SECTIONS.with(|sections| {
+        sections
+            .borrow()
+            .iter()
+            .find(|section| !level.is_lower_than(section.level()))
+            .cloned()
+    })?;
+    Some(HierarchyViolation::held_while_blocking(
+        section,
+        Some(attempted()),
+        held_locks(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::Mutex;
+
+    #[test]
+    fn no_locks_held() {
+        let mutex = Mutex::new(());
+        drop(mutex.lock().unwrap());
+        assert_no_locks_held();
+        let _section = BlockingSection::new();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn assert_no_locks_held_while_holding_a_lock() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::with_level((), 3);
+                let _guard = mutex.lock().unwrap();
+                // Must panic, a lock is held
+                assert_no_locks_held();
+            }),
+            (None, vec![3])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn enter_section_while_holding_a_lock() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::new(());
+                let _guard = mutex.lock().unwrap();
+                // Must panic, a lock is held
+                let _section = BlockingSection::new();
+            }),
+            (None, vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn acquire_lock_within_section() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::with_level((), 2);
+                let _section = BlockingSection::with_level(2);
+                // Must panic, level 2 must not be held while blocking
+                let _guard = mutex.lock().unwrap();
+            }),
+            (Some(2), vec![])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn negative_levels_count_as_held() {
+        let mutex = Mutex::with_level((), -1);
+        let _guard = mutex.lock().unwrap();
+        let error = std::panic::catch_unwind(assert_no_locks_held).unwrap_err();
+        let violation = error.downcast::<HierarchyViolation>().unwrap();
+        assert_eq!(1, violation.held().len());
+        assert!(violation
+            .to_string()
+            .contains("No locks may be held while blocking."));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn acquire_negative_level_within_section() {
+        let mutex = Mutex::with_level((), -1);
+        let _section = BlockingSection::new();
+        let error = std::panic::catch_unwind(|| mutex.lock().map(drop)).unwrap_err();
+        let violation = error.downcast::<HierarchyViolation>().unwrap();
+        assert_eq!(
+            Some(-1),
+            violation
+                .attempted()
+                .and_then(|info| info.level().as_integer())
+        );
+    }
+
+    #[test]
+    fn lower_levels_may_be_held_within_section() {
+        let mutex_a = Mutex::with_level((), 1);
+        let mutex_b = Mutex::with_level((), 0);
+        let _guard_a = mutex_a.lock().unwrap();
+        let _section = BlockingSection::with_level(2);
+        // Fine: 0 is lower than 2
+        let _guard_b = mutex_b.lock().unwrap();
+    }
+
+    #[test]
+    fn section_ends_once_dropped() {
+        let mutex = Mutex::new(());
+        {
+            let _section = BlockingSection::new();
+        }
+        // Fine, the section has already ended
+        let _guard = mutex.lock().unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    sync::LockResult,
+    time::{Duration, Instant},
+};
+
+use crate::{map_guard, MutexGuard};
+
+/// Wrapper around a [`std::sync::Condvar`] which works with the hierarchy checked [`MutexGuard`].
+///
+/// While a thread is parked, the level of the mutex is removed from the thread local stack. Once
+/// the thread wakes up and the mutex is acquired again, its level is checked against the locks
+/// held at that time. The conditions of [`Condvar::wait_while`] and [`Condvar::wait_timeout_while`]
+/// run with the mutex and its level held, so locks acquired within them are checked, too.
+///
+/// ```
+/// use std::{sync::Arc, thread};
+/// use lock_hierarchy::{Condvar, Mutex};
+///
+/// let pair = Arc::new((Mutex::new(false), Condvar::new()));
+/// let pair2 = Arc::clone(&pair);
+///
+/// thread::spawn(move || {
+///     let (lock, cvar) = &*pair2;
+///     *lock.lock().unwrap() = true;
+///     cvar.notify_one();
+/// });
+///
+/// let (lock, cvar) = &*pair;
+/// let _started = cvar.wait_while(lock.lock().unwrap(), |started| !*started).unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct Condvar {
+    inner: std::sync::Condvar,
+}
+
+impl Condvar {
+    /// See [std::sync::Condvar::new]
+    pub const fn new() -> Self {
+        Self {
+            inner: std::sync::Condvar::new(),
+        }
+    }
+
+    /// See [std::sync::Condvar::wait]
+    #[track_caller]
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let MutexGuard {
+            inner,
+            mutex,
+            mut _level,
+        } = guard;
+        let result = _level.released(|| self.inner.wait(inner));
+        map_guard(result, |inner| MutexGuard {
+            inner,
+            mutex,
+            _level,
+        })
+    }
+
+    /// See [std::sync::Condvar::wait_while]
+    #[track_caller]
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        // Like std, but the level is released only while parked
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// See [std::sync::Condvar::wait_timeout]
+    #[track_caller]
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let MutexGuard {
+            inner,
+            mutex,
+            mut _level,
+        } = guard;
+        let result = _level.released(|| self.inner.wait_timeout(inner, dur));
+        map_guard(result, |(inner, timeout)| {
+            (
+                MutexGuard {
+                    inner,
+                    mutex,
+                    _level,
+                },
+                WaitTimeoutResult(timeout.timed_out()),
+            )
+        })
+    }
+
+    /// See [std::sync::Condvar::wait_timeout_while]
+    #[track_caller]
+    pub fn wait_timeout_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        dur: Duration,
+        mut condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        // Like std, but the level is released only while parked
+        let start = Instant::now();
+        loop {
+            if !condition(&mut *guard) {
+                return Ok((guard, WaitTimeoutResult(false)));
+            }
+            let Some(timeout) = dur.checked_sub(start.elapsed()) else {
+                return Ok((guard, WaitTimeoutResult(true)));
+            };
+            guard = self.wait_timeout(guard, timeout)?.0;
+        }
+    }
+
+    /// See [std::sync::Condvar::notify_one]
+    pub fn notify_one(&self) {
+        self.inner.notify_one()
+    }
+
+    /// See [std::sync::Condvar::notify_all]
+    pub fn notify_all(&self) {
+        self.inner.notify_all()
+    }
+}
+
+/// Whether a timed wait of a [`Condvar`] returned because of the timeout. Like
+/// [`std::sync::WaitTimeoutResult`], which can not be constructed outside of std.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// See [std::sync::WaitTimeoutResult::timed_out]
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::Mutex;
+
+    #[test]
+    fn wait_for_notification() {
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = thread::spawn({
+            let pair = pair.clone();
+            move || {
+                let (mutex, condvar) = &*pair;
+                *mutex.lock().unwrap() = true;
+                condvar.notify_all();
+            }
+        });
+        let (mutex, condvar) = &*pair;
+        let mut guard = mutex.lock().unwrap();
+        while !*guard {
+            guard = condvar.wait(guard).unwrap();
+        }
+        drop(guard);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn wait_timeout_while() {
+        let mutex = Mutex::new(0);
+        let condvar = Condvar::new();
+        let guard = mutex.lock().unwrap();
+        let (guard, timeout) = condvar
+            .wait_timeout_while(guard, Duration::from_millis(1), |value| *value == 0)
+            .unwrap();
+        assert!(timeout.timed_out());
+        assert_eq!(0, *guard);
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn level_is_released_while_parked() {
+        let pair = Arc::new((Mutex::with_level(false, 1), Condvar::new()));
+        let (mutex, condvar) = &*pair;
+        let mut guard = mutex.lock().unwrap();
+        // Can not lock the mutex before this thread is parked, so it waits at least once
+        let thread = thread::spawn({
+            let pair = pair.clone();
+            move || {
+                let (mutex, condvar) = &*pair;
+                *mutex.lock().unwrap() = true;
+                condvar.notify_all();
+            }
+        });
+        let line = line!() + 2;
+        while !*guard {
+            guard = condvar.wait(guard).unwrap();
+        }
+        // The level has been removed while parked and acquired again by the wait
+        let held = crate::held_locks();
+        assert_eq!(1, held.len());
+        assert_eq!(
+            (file!(), line),
+            (held[0].location().file(), held[0].location().line())
+        );
+        drop(guard);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn level_is_tracked_after_wakeup() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Mutex::new(());
+                let mutex_b = Mutex::new(());
+                let condvar = Condvar::new();
+                let (guard_a, _) = condvar
+                    .wait_timeout(mutex_a.lock().unwrap(), Duration::from_millis(1))
+                    .unwrap();
+                // Must panic, mutex a is held again after waking up
+                let _guard_b = mutex_b.lock().unwrap();
+                drop(guard_a);
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    fn wait_while_condition_holds() {
+        let mutex = Mutex::new(0);
+        let condvar = Condvar::new();
+        let guard = condvar
+            .wait_while(mutex.lock().unwrap(), |_| false)
+            .unwrap();
+        assert_eq!(0, *guard);
+        drop(guard);
+        let (_guard, timeout) = condvar
+            .wait_timeout_while(mutex.lock().unwrap(), Duration::from_secs(60), |_| false)
+            .unwrap();
+        assert!(!timeout.timed_out());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn locks_acquired_by_condition_are_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::with_level((), 1);
+                let higher = Mutex::with_level((), 2);
+                let condvar = Condvar::new();
+                // Must panic, the condition runs while level 1 is held
+                let _ = condvar.wait_while(mutex.lock().unwrap(), |_| {
+                    let _higher = higher.lock().unwrap();
+                    false
+                });
+            }),
+            (Some(2), vec![1])
+        );
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::with_level((), 1);
+                let higher = Mutex::with_level((), 2);
+                let condvar = Condvar::new();
+                // Must panic, the condition runs while level 1 is held
+                let _ = condvar.wait_timeout_while(
+                    mutex.lock().unwrap(),
+                    Duration::from_millis(1),
+                    |_| {
+                        let _higher = higher.lock().unwrap();
+                        false
+                    },
+                );
+            }),
+            (Some(2), vec![1])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn reacquire_is_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Mutex::with_level((), 1);
+                let mutex_b = Mutex::new(());
+                let condvar = Condvar::new();
+                let guard_a = mutex_a.lock().unwrap();
+                let _guard_b = mutex_b.lock().unwrap();
+                // Must panic, waiting reacquires level 1 while level 0 is held
+                let _ = condvar.wait_timeout(guard_a, Duration::from_millis(1));
+            }),
+            (Some(1), vec![0])
+        );
+    }
+}
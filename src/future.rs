@@ -0,0 +1,91 @@
+//! Asynchronous locks, which check the lock hierarchy per task rather than per thread.
+//!
+//! A task may be polled by different threads, and many tasks interleave on a single thread. The
+//! thread local stack used by the blocking locks therefore does not tell which locks a task holds.
+//! Wrap each task in [`task_scope`] before spawning it. The returned future keeps the levels held
+//! by the task between polls and puts them on top of the stack of whichever thread polls it. This
+//! way acquisitions within the task are checked against the locks held by the task, as well as the
+//! locks held by the polling thread.
+//!
+//! Guards of asynchronous locks are `Send`, so they may be handed to another task or thread. The
+//! level is released from the task which acquired the lock, once that task is polled again.
+//! Likewise, a pending lock future outside of any task scope may be resumed by another thread.
+//! Its level is then released from the previous thread and checked again on the current one.
+//!
+//! The locks in this module do not depend on any particular runtime.
+//!
+//! Holding a lock across an await point keeps it locked for as long as the task is suspended,
+//! which is both a deadlock risk and a latency bug. Wrap a future in
+//! [`check_no_locks_across_await`] to detect this in debug builds. It may wrap the
+//! [`task_scope`] of the task or be wrapped by it.
+//!
+//! ```
+//! use lock_hierarchy::future::{task_scope, Mutex};
+//!
+//! let mutex_a = Mutex::with_level((), 1);
+//! let mutex_b = Mutex::new(()); // Level 0
+//! let task = task_scope(async {
+//!     let _guard_a = mutex_a.lock().await;
+//!     // Fine: 0 is lower level than 1
+//!     let _guard_b = mutex_b.lock().await;
+//! });
+//! # let _ = task;
+//! ```
+
+mod across_await;
+mod mutex;
+mod raw;
+mod rwlock;
+mod task;
+
+pub use across_await::{check_no_locks_across_await, CheckNoLocksAcrossAwait};
+pub use mutex::{Lock, Mutex, MutexGuard};
+pub use rwlock::{Read, RwLock, RwLockReadGuard, RwLockWriteGuard, Write};
+pub use task::{task_scope, TaskScope};
+
+/// A minimal executor, so the asynchronous locks can be tested without a runtime.
+#[cfg(test)]
+pub(crate) mod executor {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Returns `Pending` once, so the task is suspended.
+    pub async fn yield_now() {
+        let mut yielded = false;
+        std::future::poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
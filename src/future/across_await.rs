@@ -0,0 +1,246 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(lock_check)]
+use std::cell::RefCell;
+
+#[cfg(lock_check)]
+use crate::{
+    level::held_locks,
+    violation::{report, HierarchyViolation},
+    LockInfo,
+};
+
+#[cfg(lock_check)]
+thread_local! {
+    /// Locks held by tasks which have been suspended during the current poll of a
+    /// [`CheckNoLocksAcrossAwait`]. Their levels are kept by their [`TaskScope`](super::TaskScope)
+    /// rather than on the thread local stack. `None` outside of such a poll.
+    static SUSPENDED: RefCell<Option<Vec<LockInfo>>> = const { RefCell::new(None) };
+}
+
+/// Records the locks still held by a task which has just been suspended.
+#[cfg(lock_check)]
+pub(crate) fn task_suspended(held: &[LockInfo]) {
+    SUSPENDED.with(|suspended| {
+        if let Some(suspended) = &mut *suspended.borrow_mut() {
+            suspended.extend_from_slice(held);
+        }
+    });
+}
+
+/// Reports a violation in debug builds if `future` returns [`Poll::Pending`] while still holding a lock it
+/// acquired during that poll. Such a lock is held across an await point and stays locked while the
+/// task is suspended. Waiting for an asynchronous lock does not count as holding it.
+///
+/// Works with blocking and asynchronous locks alike, and regardless of whether it wraps a
+/// [`task_scope`](super::task_scope) or is wrapped by one.
+///
+/// ```
+/// use lock_hierarchy::{future::check_no_locks_across_await, Mutex};
+///
+/// # async fn some_io() {}
+/// let mutex = Mutex::new(42);
+/// let future = check_no_locks_across_await(async {
+///     let value = *mutex.lock().unwrap();
+///     // Fine, the guard has already been dropped
+///     some_io().await;
+///     let _guard = mutex.lock().unwrap();
+///     // Would panic, the guard is held across the await point
+///     // some_io().await;
+/// });
+/// # let _ = future;
+/// ```
+pub fn check_no_locks_across_await<F: Future>(future: F) -> CheckNoLocksAcrossAwait<F> {
+    CheckNoLocksAcrossAwait {
+        future: Box::pin(future),
+    }
+}
+
+/// Future returned by [`check_no_locks_across_await`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+#[derive(Debug)]
+pub struct CheckNoLocksAcrossAwait<F> {
+    /// Boxed, so we can poll it without any need for unsafe pin projections.
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CheckNoLocksAcrossAwait<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        #[cfg(lock_check)]
+        let before = held_locks();
+        #[cfg(lock_check)]
+        let collect = CollectSuspended::start();
+        let poll = self.future.as_mut().poll(cx);
+        #[cfg(lock_check)]
+        if poll.is_pending() {
+            // Locks held now, which have not been held before the poll
+            let mut acquired = held_locks();
+            acquired.extend(collect.finish());
+            acquired.retain(|held| !held.is_acquiring());
+            for held in before {
+                if let Some(index) = acquired
+                    .iter()
+                    .position(|l| l.instance() == held.instance())
+                {
+                    acquired.remove(index);
+                }
+            }
+            if !acquired.is_empty() {
+                report(HierarchyViolation::held_across_await(acquired))
+            }
+        }
+        poll
+    }
+}
+
+/// Collects the locks of tasks suspended during one poll, see [`SUSPENDED`]. Restores the
+/// collection of an enclosing check once dropped, even if the poll panics.
+#[cfg(lock_check)]
+struct CollectSuspended {
+    outer: Option<Vec<LockInfo>>,
+}
+
+#[cfg(lock_check)]
+impl CollectSuspended {
+    fn start() -> Self {
+        let outer = SUSPENDED.with(|suspended| suspended.replace(Some(Vec::new())));
+        CollectSuspended { outer }
+    }
+
+    fn finish(self) -> Vec<LockInfo> {
+        SUSPENDED.with(|suspended| suspended.borrow().clone().unwrap_or_default())
+    }
+}
+
+#[cfg(lock_check)]
+impl Drop for CollectSuspended {
+    fn drop(&mut self) {
+        SUSPENDED.with(|suspended| {
+            let mut suspended = suspended.borrow_mut();
+            let inner = std::mem::replace(&mut *suspended, self.outer.take());
+            // An enclosing check sees the suspended tasks, too
+            if let (Some(outer), Some(inner)) = (&mut *suspended, inner) {
+                outer.extend(inner);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        sync::mpsc,
+        task::{Context, Waker},
+        thread,
+    };
+
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::{
+        future::executor::{block_on, yield_now},
+        Mutex,
+    };
+
+    #[test]
+    fn lock_released_before_await() {
+        let mutex = Mutex::new(42);
+        block_on(check_no_locks_across_await(async {
+            let value = *mutex.lock().unwrap();
+            yield_now().await;
+            assert_eq!(42, value);
+        }));
+    }
+
+    #[test]
+    fn waiting_for_contended_async_lock() {
+        let mutex = crate::future::Mutex::new(0);
+        thread::scope(|s| {
+            let mutex = &mutex;
+            let (locked, is_locked) = mpsc::channel();
+            // Dropped if the test fails, which unlocks the mutex, too
+            let (unlock, unlocked) = mpsc::channel::<()>();
+            s.spawn(move || {
+                let _guard = mutex.try_lock().unwrap();
+                locked.send(()).unwrap();
+                let _ = unlocked.recv();
+            });
+            is_locked.recv().unwrap();
+            let mut task = pin!(crate::future::task_scope(check_no_locks_across_await(
+                async { *mutex.lock().await += 1 }
+            )));
+            let mut cx = Context::from_waker(Waker::noop());
+            // Fine, the task waits for the lock, but does not hold it
+            assert!(task.as_mut().poll(&mut cx).is_pending());
+            unlock.send(()).unwrap();
+            while task.as_mut().poll(&mut cx).is_pending() {
+                thread::yield_now();
+            }
+        });
+        assert_eq!(1, mutex.into_inner());
+    }
+
+    #[test]
+    fn lock_held_before_polling() {
+        let mutex = Mutex::with_level((), 1);
+        let _guard = mutex.lock().unwrap();
+        // Fine, the lock has not been acquired by the future
+        block_on(check_no_locks_across_await(yield_now()));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn lock_held_across_await() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::with_level((), 1);
+                block_on(check_no_locks_across_await(async {
+                    let _guard = mutex.lock().unwrap();
+                    yield_now().await;
+                }));
+            }),
+            (None, vec![1])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn lock_held_across_await_within_task_scope() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = crate::future::Mutex::new(());
+                block_on(check_no_locks_across_await(crate::future::task_scope(
+                    async {
+                        let _guard = mutex.lock().await;
+                        yield_now().await;
+                    },
+                )));
+            }),
+            (None, vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn async_lock_held_across_await() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = crate::future::Mutex::new(());
+                block_on(crate::future::task_scope(check_no_locks_across_await(
+                    async {
+                        let _guard = mutex.lock().await;
+                        yield_now().await;
+                    },
+                )));
+            }),
+            (None, vec![0])
+        );
+    }
+}
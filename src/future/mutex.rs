@@ -0,0 +1,401 @@
+use std::{
+    cell::UnsafeCell,
+    fmt::{Debug, Display, Formatter},
+    future::Future,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::raw::RawLock;
+use crate::level::{Level, TaskLevelGuard};
+
+/// Asynchronous mutex, which checks the lock hierarchy of the task acquiring it.
+///
+/// See the [module level documentation](crate::future) for more information.
+///
+/// ```
+/// use lock_hierarchy::future::{task_scope, Mutex};
+///
+/// let mutex_a = Mutex::new(()); // Level 0
+/// let mutex_b = Mutex::with_level((), 0); // also level 0
+/// let task = task_scope(async {
+///     // Fine, first mutex in task
+///     let _guard_a = mutex_a.lock().await;
+///     // Would panic, lock hierarchy violation
+///     // let _guard_b = mutex_b.lock().await;
+/// });
+/// # let _ = task;
+/// ```
+pub struct Mutex<T> {
+    raw: RawLock,
+    level: Level,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: Access to `data` is synchronized by `raw`, like it is for `std::sync::Mutex`.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates lock with level 0. Use this constructor if you want to get an error in debug builds
+    /// every time you acquire another lock while holding this one.
+    pub const fn new(t: T) -> Self {
+        Self::with_level(t, 0)
+    }
+
+    /// Creates a lock and assigns it a level in the lock hierarchy. Higher levels must be acquired
+    /// first if locks are to be held simultaneously. This way we can ensure locks are always
+    /// acquired in the same order. This prevents deadlocks.
+    ///
+    /// Unlike the blocking locks, this takes a `u32` only, so it can be used in statics. Custom
+    /// [`LockLevel`](crate::LockLevel)s, e.g. enums, are not supported.
+    pub const fn with_level(t: T, level: u32) -> Self {
+        Mutex {
+            raw: RawLock::new(),
+            level: Level::new(level).protecting::<T>(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the lock. The name is part of every violation
+    /// report involving this lock, next to the name of the protected type.
+    ///
+    /// Takes a `u32` level only, like [`Self::with_level`].
+    pub const fn with_name_and_level(t: T, name: &'static str, level: u32) -> Self {
+        Mutex {
+            raw: RawLock::new(),
+            level: Level::new(level).named(name).protecting::<T>(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    /// Acquires the mutex, suspending the task until it is available. The lock hierarchy is
+    /// checked on the first poll, before waiting.
+    #[track_caller]
+    pub fn lock(&self) -> Lock<'_, T> {
+        Lock {
+            mutex: self,
+            location: Location::caller(),
+            level: None,
+        }
+    }
+
+    /// Attempts to acquire the mutex without waiting.
+    ///
+    /// A non blocking attempt can not deadlock, so it may happen out of hierarchy order. Once
+    /// acquired, the lock is tracked like any other and later acquisitions are checked against it.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.level.lock_unchecked();
+        self.raw.try_lock(true).then(|| MutexGuard {
+            mutex: self,
+            _level: level.into_task_guard(),
+            _data: PhantomData,
+        })
+    }
+
+    /// Whether the current task holds this mutex. Always `false` if lock hierarchies are not
+    /// checked, e.g. in release builds, so only use it for assertions in debug builds.
+    pub fn is_held_by_current_thread(&self) -> bool {
+        self.level.is_held(false)
+    }
+
+    /// Panics in debug builds if the current thread does not hold this mutex. Use it to ensure the
+    /// caller of a function has locked the mutex.
+    #[track_caller]
+    pub fn assert_held(&self) {
+        self.level.assert_held(false)
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    pub fn get_mut(&mut self) -> &mut T {
+        // No need to check hierarchy, this does not lock
+        self.data.get_mut()
+    }
+
+    /// Consumes this mutex, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        // No need to check hierarchy, this does not lock
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    /// Creates a new mutex in an unlocked state ready for use.
+    /// This is equivalent to [`Mutex::new`].
+    fn from(value: T) -> Self {
+        Mutex::new(value)
+    }
+}
+
+impl<T> Debug for Mutex<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mutex")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`Mutex::lock`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Lock<'a, T> {
+    mutex: &'a Mutex<T>,
+    location: &'static Location<'static>,
+    level: Option<TaskLevelGuard<'a>>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<MutexGuard<'a, T>> {
+        let this = self.get_mut();
+        let mutex = this.mutex;
+        mutex
+            .raw
+            .poll_lock(true, &mutex.level, this.location, &mut this.level, cx)
+            .map(|level| MutexGuard {
+                mutex,
+                _level: level,
+                _data: PhantomData,
+            })
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    _level: TaskLevelGuard<'a>,
+    /// Only `Sync` if `T` is `Sync`, since the guard hands out `&T`.
+    _data: PhantomData<&'a mut T>,
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.raw.unlock(true);
+    }
+}
+
+impl<T: Debug> Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T: Display> Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard holds the lock exclusively
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The guard holds the lock exclusively
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Waker},
+        thread,
+    };
+
+    use super::*;
+    use crate::future::{
+        executor::{block_on, yield_now},
+        task_scope,
+    };
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+
+    #[test]
+    fn acquire_resource() {
+        let mutex = Mutex::new(42);
+        let guard = block_on(task_scope(mutex.lock()));
+        assert_eq!(42, *guard);
+    }
+
+    #[test]
+    fn allow_mutation() {
+        let mutex = Mutex::new(42);
+        block_on(task_scope(async {
+            let mut guard = mutex.lock().await;
+            *guard = 43;
+        }));
+        assert_eq!(43, mutex.into_inner());
+    }
+
+    #[test]
+    fn multithreaded() {
+        let mutex = Arc::new(Mutex::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mutex = mutex.clone();
+                thread::spawn(move || {
+                    block_on(task_scope(async {
+                        for _ in 0..100 {
+                            let mut guard = mutex.lock().await;
+                            yield_now().await;
+                            *guard += 1;
+                        }
+                    }))
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(400, *block_on(task_scope(mutex.lock())));
+    }
+
+    #[test]
+    fn guard_dropped_by_another_task() {
+        let mutex = Mutex::with_level(0, 1);
+        let stored = std::sync::Mutex::new(None);
+        let mut task = pin!(task_scope(async {
+            *stored.lock().unwrap() = Some(mutex.lock().await);
+            yield_now().await;
+            // Fine, the guard has been dropped by the other task
+            *mutex.lock().await += 1;
+            #[cfg(lock_check)]
+            assert_eq!(0, crate::held_lock_count());
+        }));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(task.as_mut().poll(&mut cx).is_pending());
+        block_on(task_scope(async {
+            *stored.lock().unwrap().take().unwrap() += 1;
+        }));
+        assert!(task.as_mut().poll(&mut cx).is_ready());
+
+        // Dropped by a thread outside of any task
+        *stored.lock().unwrap() = Some(block_on(task_scope(mutex.lock())));
+        thread::scope(|s| {
+            s.spawn(|| drop(stored.lock().unwrap().take()));
+        });
+        #[cfg(lock_check)]
+        assert_eq!(0, crate::held_lock_count());
+        assert_eq!(2, *block_on(task_scope(mutex.lock())));
+    }
+
+    #[test]
+    fn lock_resumed_by_another_thread() {
+        let mutex = Mutex::with_level(0, 1);
+        let guard = block_on(task_scope(mutex.lock()));
+        thread::scope(|s| {
+            let (lock_sender, lock_receiver) = std::sync::mpsc::channel();
+            let (done_sender, done_receiver) = std::sync::mpsc::channel::<()>();
+            let mutex = &mutex;
+            s.spawn(move || {
+                // Not within a task, so the level is acquired on this thread
+                let mut lock = Box::pin(mutex.lock());
+                let mut cx = Context::from_waker(Waker::noop());
+                assert!(lock.as_mut().poll(&mut cx).is_pending());
+                lock_sender.send(lock).unwrap();
+                done_receiver.recv().unwrap_err();
+                // Fine, the lock has been acquired and released by the other thread
+                #[cfg(lock_check)]
+                assert_eq!(0, crate::held_lock_count());
+            });
+            let mut lock = lock_receiver.recv().unwrap();
+            drop(guard);
+            s.spawn(move || {
+                let mut cx = Context::from_waker(Waker::noop());
+                let Poll::Ready(mut guard) = lock.as_mut().poll(&mut cx) else {
+                    panic!("Expected the mutex to be unlocked");
+                };
+                #[cfg(lock_check)]
+                assert_eq!(1, crate::held_lock_count());
+                *guard += 1;
+                drop(guard);
+                #[cfg(lock_check)]
+                assert_eq!(0, crate::held_lock_count());
+                drop(done_sender);
+            });
+        });
+        assert_eq!(1, *block_on(task_scope(mutex.lock())));
+    }
+
+    #[test]
+    fn try_lock() {
+        let mutex_a = Mutex::new(());
+        let mutex_b = Mutex::with_level((), 1);
+        block_on(task_scope(async {
+            let _guard_a = mutex_a.lock().await;
+            assert!(mutex_a.try_lock().is_none());
+            // Fine, try_lock does not wait and can therefore not deadlock
+            let _guard_b = mutex_b.try_lock().unwrap();
+        }));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn self_deadlock() {
+        assert_eq!(
+            catch_violation(|| {
+                // This ensures the level is checked before waiting, which would otherwise never finish
+                let mutex = Mutex::new(());
+                block_on(task_scope(async {
+                    let _guard_a = mutex.lock().await;
+                    let _guard_b = mutex.lock().await;
+                }));
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn violation_reports_location_of_lock_call() {
+        let mutex_a = Mutex::new(());
+        let mutex_b = Mutex::new(());
+        let line = line!() + 3;
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            block_on(task_scope(async {
+                let _guard_a = mutex_a.lock().await;
+                let _guard_b = mutex_b.lock().await;
+            }))
+        }))
+        .unwrap_err();
+        let violation = payload.downcast::<crate::HierarchyViolation>().unwrap();
+        let held = violation.held()[0].location();
+        assert_eq!((file!(), line), (held.file(), held.line()));
+        let attempted = violation.attempted().unwrap().location();
+        assert_eq!((file!(), line + 1), (attempted.file(), attempted.line()));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn checked_against_blocking_locks() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = crate::Mutex::new(());
+                let mutex_b = Mutex::new(());
+                let _guard_a = mutex_a.lock().unwrap();
+                block_on(task_scope(async {
+                    let _guard_b = mutex_b.lock().await;
+                }));
+            }),
+            (Some(0), vec![0])
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    panic::Location,
+    sync::PoisonError,
+    task::{Context, Poll, Waker},
+};
+
+use crate::level::{Level, TaskLevelGuard};
+
+/// Reader writer lock which suspends tasks, rather than blocking threads.
+///
+/// All waiting tasks are woken once the lock is released and race for it again. This is not fair,
+/// but no wake up is lost if a waiting future is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct RawLock {
+    state: std::sync::Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    waiters: Vec<Waker>,
+}
+
+impl State {
+    fn acquire(&mut self, exclusive: bool) -> bool {
+        if self.writer || (exclusive && self.readers != 0) {
+            return false;
+        }
+        if exclusive {
+            self.writer = true;
+        } else {
+            self.readers += 1;
+        }
+        true
+    }
+}
+
+impl RawLock {
+    pub const fn new() -> Self {
+        Self {
+            state: std::sync::Mutex::new(State {
+                readers: 0,
+                writer: false,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // The state is consistent after every statement, so a panic can not leave it corrupted
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn try_lock(&self, exclusive: bool) -> bool {
+        self.state().acquire(exclusive)
+    }
+
+    /// Acquires the level on the first poll, so the hierarchy is checked before waiting. Until the
+    /// lock is acquired, the level is marked as acquiring, since the task does not hold the lock
+    /// yet. Once the lock is acquired, the level guard is handed over to the caller.
+    ///
+    /// A future outside of any [`task_scope`](crate::future::task_scope) may be resumed by another
+    /// thread than the one it has acquired the level on. In that case the level is released from
+    /// the previous thread and acquired and checked again on the current one.
+    pub fn poll_lock<'a>(
+        &self,
+        exclusive: bool,
+        level: &'a Level,
+        location: &'static Location<'static>,
+        level_guard: &mut Option<TaskLevelGuard<'a>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TaskLevelGuard<'a>> {
+        if level_guard
+            .as_ref()
+            .is_some_and(|guard| !guard.is_tracked_here())
+        {
+            *level_guard = None;
+        }
+        if level_guard.is_none() {
+            let guard = level.lock_at(location);
+            let guard = if exclusive { guard } else { guard.shared() };
+            *level_guard = Some(guard.acquiring(true).into_task_guard());
+        }
+        let mut state = self.state();
+        if state.acquire(exclusive) {
+            let guard = level_guard.take().expect("Level is acquired above");
+            return Poll::Ready(guard.acquiring(false));
+        }
+        if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+            state.waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    pub fn unlock(&self, exclusive: bool) {
+        let waiters = {
+            let mut state = self.state();
+            if exclusive {
+                state.writer = false;
+            } else {
+                state.readers -= 1;
+            }
+            std::mem::take(&mut state.waiters)
+        };
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
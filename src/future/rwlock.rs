@@ -0,0 +1,405 @@
+use std::{
+    cell::UnsafeCell,
+    fmt::{Debug, Display, Formatter},
+    future::Future,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::raw::RawLock;
+use crate::level::{Level, TaskLevelGuard};
+
+/// Asynchronous reader writer lock, which checks the lock hierarchy of the task acquiring it.
+///
+/// See the [module level documentation](crate::future) for more information.
+///
+/// ```
+/// use lock_hierarchy::future::{task_scope, RwLock};
+///
+/// let mutex_a = RwLock::new(()); // Level 0
+/// let mutex_b = RwLock::with_level((), 0); // also level 0
+/// let task = task_scope(async {
+///     // Fine, first mutex in task
+///     let _guard_a = mutex_a.read().await;
+///     // Would panic, lock hierarchy violation
+///     // let _guard_b = mutex_b.read().await;
+/// });
+/// # let _ = task;
+/// ```
+pub struct RwLock<T> {
+    raw: RawLock,
+    level: Level,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: Access to `data` is synchronized by `raw`, like it is for `std::sync::RwLock`.
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a lock with level 0. Use this constructor if you want to get an error in debug builds
+    /// every time you acquire another lock while holding this one.
+    pub const fn new(t: T) -> Self {
+        Self::with_level(t, 0)
+    }
+
+    /// Creates a lock and assigns it a level in the lock hierarchy. Higher levels must be acquired
+    /// first if locks are to be held simultaneously. This way we can ensure locks are always
+    /// acquired in the same order. This prevents deadlocks.
+    ///
+    /// Unlike the blocking locks, this takes a `u32` only, so it can be used in statics. Custom
+    /// [`LockLevel`](crate::LockLevel)s, e.g. enums, are not supported.
+    pub const fn with_level(t: T, level: u32) -> Self {
+        RwLock {
+            raw: RawLock::new(),
+            level: Level::new(level).protecting::<T>(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the lock. The name is part of every violation
+    /// report involving this lock, next to the name of the protected type.
+    ///
+    /// Takes a `u32` level only, like [`Self::with_level`].
+    pub const fn with_name_and_level(t: T, name: &'static str, level: u32) -> Self {
+        RwLock {
+            raw: RawLock::new(),
+            level: Level::new(level).named(name).protecting::<T>(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    /// Acquires shared read access, suspending the task until it is available. The lock hierarchy
+    /// is checked on the first poll, before waiting.
+    #[track_caller]
+    pub fn read(&self) -> Read<'_, T> {
+        Read {
+            lock: self,
+            location: Location::caller(),
+            level: None,
+        }
+    }
+
+    /// Acquires exclusive write access, suspending the task until it is available. The lock
+    /// hierarchy is checked on the first poll, before waiting.
+    #[track_caller]
+    pub fn write(&self) -> Write<'_, T> {
+        Write {
+            lock: self,
+            location: Location::caller(),
+            level: None,
+        }
+    }
+
+    /// Attempts to acquire shared read access without waiting.
+    ///
+    /// A non blocking attempt can not deadlock, so it may happen out of hierarchy order. Once
+    /// acquired, the lock is tracked like any other and later acquisitions are checked against it.
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.level.lock_unchecked().shared();
+        self.raw.try_lock(false).then(|| RwLockReadGuard {
+            lock: self,
+            _level: level.into_task_guard(),
+            _data: PhantomData,
+        })
+    }
+
+    /// Attempts to acquire exclusive write access without waiting.
+    ///
+    /// A non blocking attempt can not deadlock, so it may happen out of hierarchy order. Once
+    /// acquired, the lock is tracked like any other and later acquisitions are checked against it.
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.level.lock_unchecked();
+        self.raw.try_lock(true).then(|| RwLockWriteGuard {
+            lock: self,
+            _level: level.into_task_guard(),
+            _data: PhantomData,
+        })
+    }
+
+    /// Whether the current thread holds this lock for reading. Always `false` if lock hierarchies
+    /// are not checked, e.g. in release builds, so only use it for assertions in debug builds.
+    pub fn is_read_held(&self) -> bool {
+        self.level.is_held(true)
+    }
+
+    /// Whether the current thread holds this lock for writing. Always `false` if lock hierarchies
+    /// are not checked, e.g. in release builds, so only use it for assertions in debug builds.
+    pub fn is_write_held(&self) -> bool {
+        self.level.is_held(false)
+    }
+
+    /// Panics in debug builds if the current thread does not hold this lock for reading.
+    #[track_caller]
+    pub fn assert_read_held(&self) {
+        self.level.assert_held(true)
+    }
+
+    /// Panics in debug builds if the current thread does not hold this lock for writing.
+    #[track_caller]
+    pub fn assert_write_held(&self) {
+        self.level.assert_held(false)
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    pub fn get_mut(&mut self) -> &mut T {
+        // No need to check hierarchy, this does not lock
+        self.data.get_mut()
+    }
+
+    /// Consumes this lock, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        // No need to check hierarchy, this does not lock
+        self.data.into_inner()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    /// Creates a new lock in an unlocked state ready for use.
+    /// This is equivalent to [`RwLock::new`].
+    fn from(value: T) -> Self {
+        RwLock::new(value)
+    }
+}
+
+impl<T> Debug for RwLock<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RwLock")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`RwLock::read`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Read<'a, T> {
+    lock: &'a RwLock<T>,
+    location: &'static Location<'static>,
+    level: Option<TaskLevelGuard<'a>>,
+}
+
+impl<'a, T> Future for Read<'a, T> {
+    type Output = RwLockReadGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RwLockReadGuard<'a, T>> {
+        let this = self.get_mut();
+        let lock = this.lock;
+        lock.raw
+            .poll_lock(false, &lock.level, this.location, &mut this.level, cx)
+            .map(|level| RwLockReadGuard {
+                lock,
+                _level: level,
+                _data: PhantomData,
+            })
+    }
+}
+
+/// Future returned by [`RwLock::write`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Write<'a, T> {
+    lock: &'a RwLock<T>,
+    location: &'static Location<'static>,
+    level: Option<TaskLevelGuard<'a>>,
+}
+
+impl<'a, T> Future for Write<'a, T> {
+    type Output = RwLockWriteGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RwLockWriteGuard<'a, T>> {
+        let this = self.get_mut();
+        let lock = this.lock;
+        lock.raw
+            .poll_lock(true, &lock.level, this.location, &mut this.level, cx)
+            .map(|level| RwLockWriteGuard {
+                lock,
+                _level: level,
+                _data: PhantomData,
+            })
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _level: TaskLevelGuard<'a>,
+    _data: PhantomData<&'a T>,
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock(false);
+    }
+}
+
+impl<T: Debug> Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T: Display> Display for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard holds shared access, so there is no writer
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _level: TaskLevelGuard<'a>,
+    /// Only `Sync` if `T` is `Sync`, since the guard hands out `&T`.
+    _data: PhantomData<&'a mut T>,
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock(true);
+    }
+}
+
+impl<T: Debug> Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T: Display> Display for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard holds the lock exclusively
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The guard holds the lock exclusively
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    use crate::future::{
+        executor::{block_on, yield_now},
+        task_scope,
+    };
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+
+    #[test]
+    fn acquire_resource() {
+        let lock = RwLock::new(42);
+        block_on(task_scope(async {
+            assert_eq!(42, *lock.read().await);
+            assert_eq!(42, *lock.write().await);
+        }));
+    }
+
+    #[test]
+    fn allow_mutation() {
+        let lock = RwLock::new(42);
+        block_on(task_scope(async {
+            let mut guard = lock.write().await;
+            *guard = 43;
+        }));
+        assert_eq!(43, lock.into_inner());
+    }
+
+    #[test]
+    fn multithreaded() {
+        let lock = Arc::new(RwLock::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    block_on(task_scope(async {
+                        for _ in 0..100 {
+                            let value = *lock.read().await;
+                            yield_now().await;
+                            let mut guard = lock.write().await;
+                            assert!(*guard >= value);
+                            *guard += 1;
+                        }
+                    }))
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(400, *block_on(task_scope(lock.read())));
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock_a = RwLock::new(());
+        let lock_b = RwLock::with_level((), 1);
+        block_on(task_scope(async {
+            let _guard_a = lock_a.read().await;
+            assert!(lock_a.try_write().is_none());
+            // Fine, non blocking attempts can not deadlock
+            let _guard_b = lock_b.try_read().unwrap();
+        }));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn self_deadlock_write() {
+        assert_eq!(
+            catch_violation(|| {
+                // This ensures the level is checked before waiting, which would otherwise never finish
+                let lock = RwLock::new(());
+                block_on(task_scope(async {
+                    let _guard_a = lock.read().await;
+                    let _guard_b = lock.write().await;
+                }));
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn self_deadlock_read() {
+        assert_eq!(
+            catch_violation(|| {
+                let lock = RwLock::new(());
+                block_on(task_scope(async {
+                    let _guard_a = lock.read().await;
+                    let _guard_b = lock.read().await;
+                }));
+            }),
+            (Some(0), vec![0])
+        );
+    }
+}
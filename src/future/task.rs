@@ -0,0 +1,207 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::level::TaskLevels;
+
+/// Tracks the locks held by `future` as the locks of one task. Wrap every task which acquires
+/// locks in a scope before spawning it.
+///
+/// See the [module level documentation](crate::future) for more information.
+pub fn task_scope<F: Future>(future: F) -> TaskScope<F> {
+    TaskScope {
+        future: Some(Box::pin(future)),
+        levels: TaskLevels::new(),
+    }
+}
+
+/// Future returned by [`task_scope`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+#[derive(Debug)]
+pub struct TaskScope<F> {
+    /// Boxed, so we can drop it within the scope of the task, without any need for unsafe pin
+    /// projections.
+    future: Option<Pin<Box<F>>>,
+    levels: TaskLevels,
+}
+
+impl<F: Future> Future for TaskScope<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        let TaskScope { future, levels } = this;
+        let poll = levels.enter(|| {
+            let poll = future
+                .as_mut()
+                .expect("TaskScope polled after completion")
+                .as_mut()
+                .poll(cx);
+            if poll.is_ready() {
+                *future = None;
+            }
+            poll
+        });
+        if poll.is_ready() {
+            // Guards returned by the task are owned by the polling thread from now on
+            levels.hand_over();
+        } else {
+            #[cfg(lock_check)]
+            super::across_await::task_suspended(levels.held());
+        }
+        poll
+    }
+}
+
+impl<F> Drop for TaskScope<F> {
+    fn drop(&mut self) {
+        // Guards owned by a cancelled task must be released from the levels of the task
+        if let Some(future) = self.future.take() {
+            self.levels.enter(|| drop(future));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Waker},
+        thread,
+    };
+
+    use super::*;
+    use crate::future::{
+        executor::{block_on, yield_now},
+        Mutex,
+    };
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+
+    #[test]
+    fn task_levels_move_between_threads() {
+        let mutex_a = Arc::new(Mutex::with_level((), 1));
+        let mutex_b = Arc::new(Mutex::new(()));
+        let mut task = Box::pin(task_scope({
+            let mutex_a = mutex_a.clone();
+            let mutex_b = mutex_b.clone();
+            async move {
+                let _guard_a = mutex_a.lock().await;
+                yield_now().await;
+                // Fine: 0 is lower level than 1, even if polled by another thread
+                let _guard_b = mutex_b.lock().await;
+            }
+        }));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(task.as_mut().poll(&mut cx).is_pending());
+        // Fine, the task holds level 1, not this thread
+        drop(crate::Mutex::with_level((), 1).lock().unwrap());
+        thread::spawn(move || {
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(task.as_mut().poll(&mut cx).is_ready())
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn task_levels_are_checked_after_moving_between_threads() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Arc::new(Mutex::new(()));
+                let mutex_b = Arc::new(Mutex::new(()));
+                let mut task = Box::pin(task_scope(async move {
+                    let _guard_a = mutex_a.lock().await;
+                    yield_now().await;
+                    // Must panic, the task still holds level 0
+                    let _guard_b = mutex_b.lock().await;
+                }));
+                let mut cx = Context::from_waker(Waker::noop());
+                assert!(task.as_mut().poll(&mut cx).is_pending());
+                let result = thread::spawn(move || {
+                    let mut cx = Context::from_waker(Waker::noop());
+                    task.as_mut().poll(&mut cx).is_ready()
+                })
+                .join();
+                std::panic::resume_unwind(result.unwrap_err());
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    fn interleaved_tasks() {
+        let mutex_a = Mutex::new(());
+        let mutex_b = Mutex::new(());
+        let task_a = pin!(task_scope(async {
+            let _guard = mutex_a.lock().await;
+            yield_now().await;
+        }));
+        let task_b = pin!(task_scope(async {
+            let _guard = mutex_b.lock().await;
+            yield_now().await;
+        }));
+        let mut tasks: [Option<Pin<&mut dyn Future<Output = ()>>>; 2] =
+            [Some(task_a), Some(task_b)];
+        // Fine, each task only holds one lock, even though both are polled by the same thread
+        block_on(std::future::poll_fn(|cx| {
+            for slot in &mut tasks {
+                if slot
+                    .as_mut()
+                    .is_some_and(|task| task.as_mut().poll(cx).is_ready())
+                {
+                    *slot = None;
+                }
+            }
+            if tasks.iter().all(Option::is_none) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }));
+    }
+
+    #[test]
+    fn guard_returned_by_task() {
+        let mutex = Mutex::new(42);
+        let guard = block_on(task_scope(mutex.lock()));
+        assert_eq!(42, *guard);
+    }
+
+    #[test]
+    fn task_drops_guard_held_by_thread() {
+        let mutex_a = Mutex::with_level((), 1);
+        let mutex_b = Mutex::new(());
+        let guard_a = block_on(task_scope(mutex_a.lock()));
+        let mut task = Box::pin(task_scope(async {
+            let _guard_b = mutex_b.lock().await;
+            drop(guard_a);
+            yield_now().await;
+        }));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(task.as_mut().poll(&mut cx).is_pending());
+        // Fine, the thread released level 1 and the task holds level 0
+        #[cfg(lock_check)]
+        assert_eq!(0, crate::held_lock_count());
+        drop(crate::Mutex::new(()).lock().unwrap());
+        assert!(task.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn cancelled_task_releases_its_levels() {
+        let mutex = Mutex::new(());
+        let mut task = Box::pin(task_scope(async {
+            let _guard = mutex.lock().await;
+            yield_now().await;
+        }));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(task.as_mut().poll(&mut cx).is_pending());
+        drop(task);
+        // Fine, the cancelled task released its lock
+        block_on(task_scope(async { drop(mutex.lock().await) }));
+    }
+}
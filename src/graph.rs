@@ -0,0 +1,275 @@
+#[cfg(lock_check)]
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{atomic::AtomicBool, PoisonError},
+};
+use std::{
+    fmt::{Display, Formatter},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::LockInfo;
+#[cfg(lock_check)]
+use crate::{level::held_locks, HierarchyViolation};
+
+/// Decides how acquisitions are checked. Set it with [`set_check_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CheckMode {
+    /// Check each acquisition against the levels of the locks held. This is the default.
+    #[default]
+    Levels,
+    /// Ignore levels. Instead record in a process global graph which locks have been acquired
+    /// while holding which other locks, and report a violation as soon as two locks have been
+    /// acquired in opposite orders, even by different threads at different times. Like the lock
+    /// validator of the Linux kernel, this needs no levels to be assigned.
+    Graph,
+    /// Check levels and record the graph.
+    Both,
+}
+
+impl CheckMode {
+    fn from_u8(mode: u8) -> Self {
+        match mode {
+            0 => CheckMode::Levels,
+            1 => CheckMode::Graph,
+            _ => CheckMode::Both,
+        }
+    }
+
+    /// Whether acquisitions are checked against the levels of the locks held.
+    #[cfg(lock_check)]
+    pub(crate) fn checks_levels(self) -> bool {
+        self != CheckMode::Graph
+    }
+
+    /// Whether acquisitions are recorded in the lock order graph.
+    #[cfg(lock_check)]
+    pub(crate) fn records_graph(self) -> bool {
+        self != CheckMode::Levels
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(CheckMode::Levels as u8);
+
+/// Sets how acquisitions are checked from now on, in any thread, and returns the previous mode.
+///
+/// ```
+/// use lock_hierarchy::{set_check_mode, CheckMode, Mutex};
+///
+/// let previous = set_check_mode(CheckMode::Graph);
+/// let mutex_a = Mutex::new(());
+/// let mutex_b = Mutex::new(());
+/// {
+///     // Fine, levels are not checked
+///     let _guard_a = mutex_a.lock().unwrap();
+///     let _guard_b = mutex_b.lock().unwrap();
+/// }
+/// let _guard_b = mutex_b.lock().unwrap();
+/// // Would panic, mutex_a has been acquired before mutex_b above
+/// // let _guard_a = mutex_a.lock().unwrap();
+/// # drop(_guard_b);
+/// set_check_mode(previous);
+/// ```
+pub fn set_check_mode(mode: CheckMode) -> CheckMode {
+    CheckMode::from_u8(MODE.swap(mode as u8, Ordering::Relaxed))
+}
+
+/// The current [`CheckMode`].
+pub fn check_mode() -> CheckMode {
+    CheckMode::from_u8(MODE.load(Ordering::Relaxed))
+}
+
+/// An edge of the lock order graph: A lock has been acquired while another one has been held.
+#[derive(Debug, Clone)]
+pub struct ObservedOrder {
+    held: LockInfo,
+    acquired: LockInfo,
+}
+
+impl ObservedOrder {
+    /// The lock which has been held.
+    pub fn held(&self) -> &LockInfo {
+        &self.held
+    }
+
+    /// The lock which has been acquired while holding [`Self::held`].
+    pub fn acquired(&self) -> &LockInfo {
+        &self.acquired
+    }
+}
+
+impl Display for ObservedOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} before {}", self.held, self.acquired)
+    }
+}
+
+/// Records in which order locks have been acquired, so contradicting orders can be detected.
+///
+/// Nodes are lock instances, see [`LockInfo::instance`]. An edge from `a` to `b` means `b` has
+/// been acquired while holding `a`. A cycle means the locks involved may deadlock.
+#[cfg(lock_check)]
+#[derive(Debug, Default)]
+struct Graph {
+    edges: HashMap<usize, HashMap<usize, ObservedOrder>>,
+}
+
+#[cfg(lock_check)]
+impl Graph {
+    /// Records that `acquired` is acquired while holding `held`. If this contradicts an order
+    /// observed before, nothing is recorded and the cycle is returned instead. Its first edge is
+    /// the one which would have closed it.
+    fn acquire(&mut self, held: &[LockInfo], acquired: &LockInfo) -> Option<Vec<ObservedOrder>> {
+        let new: Vec<_> = held
+            .iter()
+            .filter(|held| {
+                !self
+                    .edges
+                    .get(&held.instance())
+                    .is_some_and(|edges| edges.contains_key(&acquired.instance()))
+            })
+            .map(|held| ObservedOrder {
+                held: held.clone(),
+                acquired: acquired.clone(),
+            })
+            .collect();
+        for order in &new {
+            if let Some(path) = self.path(acquired.instance(), order.held.instance()) {
+                let mut cycle = vec![order.clone()];
+                cycle.extend(path);
+                return Some(cycle);
+            }
+        }
+        for order in new {
+            self.edges
+                .entry(order.held.instance())
+                .or_default()
+                .insert(acquired.instance(), order);
+        }
+        None
+    }
+
+    /// Shortest path of observed orders leading from lock `from` to lock `to`, if any. Empty if
+    /// both are the same lock.
+    fn path(&self, from: usize, to: usize) -> Option<Vec<ObservedOrder>> {
+        let mut predecessors: HashMap<usize, &ObservedOrder> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut path = Vec::new();
+                let mut node = to;
+                while node != from {
+                    let order = predecessors[&node];
+                    path.push(order.clone());
+                    node = order.held.instance();
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (&next, order) in self.edges.get(&node).into_iter().flatten() {
+                if next != from && !predecessors.contains_key(&next) {
+                    predecessors.insert(next, order);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Forgets everything about a lock, e.g. because it has been dropped.
+    fn remove(&mut self, instance: usize) {
+        self.edges.remove(&instance);
+        for edges in self.edges.values_mut() {
+            edges.remove(&instance);
+        }
+    }
+}
+
+#[cfg(lock_check)]
+static GRAPH: std::sync::Mutex<Option<Graph>> = std::sync::Mutex::new(None);
+/// Set once anything has been recorded, so dropping locks does not need to touch the graph before.
+#[cfg(lock_check)]
+static RECORDED: AtomicBool = AtomicBool::new(false);
+
+/// Records the acquisition in the lock order graph and returns the violation it would cause, if
+/// any.
+#[cfg(lock_check)]
+pub(crate) fn acquisition_violation(attempted: LockInfo) -> Option<HierarchyViolation> {
+    let held = held_locks();
+    if held.is_empty() {
+        return None;
+    }
+    RECORDED.store(true, Ordering::Relaxed);
+    let cycle = GRAPH
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(Graph::default)
+        .acquire(&held, &attempted)?;
+    Some(HierarchyViolation::lock_order_cycle(attempted, held, cycle))
+}
+
+/// Removes a dropped lock from the lock order graph. Its instance may be reused afterwards.
+#[cfg(lock_check)]
+pub(crate) fn remove(instance: usize) {
+    if RECORDED.load(Ordering::Relaxed) {
+        if let Some(graph) = &mut *GRAPH.lock().unwrap_or_else(PoisonError::into_inner) {
+            graph.remove(instance);
+        }
+    }
+}
+
+#[cfg(all(test, lock_check))]
+mod tests {
+    use std::{panic::Location, slice};
+
+    use super::*;
+    use crate::level::Level;
+
+    fn instances(cycle: &[ObservedOrder]) -> Vec<(usize, usize)> {
+        cycle
+            .iter()
+            .map(|order| (order.held().instance(), order.acquired().instance()))
+            .collect()
+    }
+
+    #[test]
+    fn opposite_order_is_a_cycle() {
+        let (a, b) = (Level::new(0), Level::new(0));
+        let (a, b) = (a.info(Location::caller()), b.info(Location::caller()));
+        let mut graph = Graph::default();
+
+        assert!(graph.acquire(slice::from_ref(&a), &b).is_none());
+        // Fine, same order again
+        assert!(graph.acquire(slice::from_ref(&a), &b).is_none());
+        let cycle = graph.acquire(slice::from_ref(&b), &a).unwrap();
+        assert_eq!(
+            vec![(b.instance(), a.instance()), (a.instance(), b.instance())],
+            instances(&cycle)
+        );
+    }
+
+    #[test]
+    fn transitive_cycle() {
+        let (a, b, c) = (Level::new(0), Level::new(0), Level::new(0));
+        let a = a.info(Location::caller());
+        let b = b.info(Location::caller());
+        let c = c.info(Location::caller());
+        let mut graph = Graph::default();
+
+        assert!(graph.acquire(slice::from_ref(&a), &b).is_none());
+        assert!(graph.acquire(slice::from_ref(&b), &c).is_none());
+        let cycle = graph.acquire(slice::from_ref(&c), &a).unwrap();
+        assert_eq!(3, cycle.len());
+    }
+
+    #[test]
+    fn removed_lock_is_forgotten() {
+        let (a, b) = (Level::new(0), Level::new(0));
+        let (a, b) = (a.info(Location::caller()), b.info(Location::caller()));
+        let mut graph = Graph::default();
+
+        assert!(graph.acquire(slice::from_ref(&a), &b).is_none());
+        graph.remove(a.instance());
+        assert!(graph.acquire(slice::from_ref(&b), &a).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt::{Display, Formatter};
+
+/// A hierarchy of named levels, as declared by [`lock_hierarchy!`](crate::lock_hierarchy).
+///
+/// Its [`Display`] implementation renders a Markdown table, e.g. for documentation.
+#[derive(Debug, Clone, Copy)]
+pub struct Hierarchy {
+    levels: &'static [(&'static str, u32)],
+}
+
+impl Hierarchy {
+    /// Distance between two levels declared by [`lock_hierarchy!`](crate::lock_hierarchy) without
+    /// an explicit value. Leaves room for locks with levels in between.
+    pub const GAP: u32 = 10;
+
+    /// Creates a hierarchy from its levels, from highest to lowest.
+    pub const fn new(levels: &'static [(&'static str, u32)]) -> Self {
+        Hierarchy { levels }
+    }
+
+    /// Names and values of the levels, from highest to lowest.
+    pub fn levels(&self) -> &'static [(&'static str, u32)] {
+        self.levels
+    }
+
+    /// Value of the level with the given name.
+    pub fn level(&self, name: &str) -> Option<u32> {
+        self.levels
+            .iter()
+            .find(|(level_name, _)| *level_name == name)
+            .map(|&(_, level)| level)
+    }
+}
+
+impl Display for Hierarchy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let values: Vec<_> = self.levels.iter().map(|(_, l)| l.to_string()).collect();
+        let value_width = values
+            .iter()
+            .map(String::len)
+            .fold("Level".len(), usize::max);
+        let name_width = self
+            .levels
+            .iter()
+            .map(|(name, _)| name.len())
+            .fold("Name".len(), usize::max);
+        write!(f, "| {:>value_width$} | {:name_width$} |", "Level", "Name")?;
+        write!(
+            f,
+            "\n|{}:|{}|",
+            "-".repeat(value_width + 1),
+            "-".repeat(name_width + 2)
+        )?;
+        for ((name, _), value) in self.levels.iter().zip(values) {
+            write!(f, "\n| {value:>value_width$} | {name:name_width$} |")?;
+        }
+        Ok(())
+    }
+}
+
+/// Declares a lock hierarchy, from highest to lowest level.
+///
+/// Each level becomes a type implementing [`typed::Level`](crate::typed::Level), ordered with
+/// [`lock_after!`](crate::lock_after), so it can be used with the [typed](crate::typed) locks.
+/// Its value is available as `Level::LEVEL` for the other locks. The lowest level is 0, each
+/// level above is [`Hierarchy::GAP`] higher than the one below, unless a value is given
+/// explicitly. Explicit values must keep the declared order, otherwise the declaration does not
+/// compile.
+///
+/// The hierarchy itself becomes a [`Hierarchy`] constant.
+///
+/// ```
+/// use lock_hierarchy::{lock_hierarchy, typed::Level, Mutex};
+///
+/// lock_hierarchy! {
+///     /// All locks of our service.
+///     pub const SERVICE: Database > Cache = 15 > Metrics;
+/// }
+///
+/// assert_eq!(25, Database::LEVEL);
+/// assert_eq!(15, Cache::LEVEL);
+/// assert_eq!(0, Metrics::LEVEL);
+///
+/// let database = Mutex::with_level((), Database::LEVEL);
+/// let metrics = Mutex::with_level((), Metrics::LEVEL);
+/// let _database = database.lock().unwrap();
+/// // Fine, Metrics is lower than Database
+/// let _metrics = metrics.lock().unwrap();
+///
+/// assert_eq!(
+///     SERVICE.to_string(),
+///     "| Level | Name     |\n\
+///      |------:|----------|\n\
+///      |    25 | Database |\n\
+///      |    15 | Cache    |\n\
+///      |     0 | Metrics  |"
+/// );
+/// ```
+///
+/// ```compile_fail
+/// # use lock_hierarchy::lock_hierarchy;
+/// lock_hierarchy! {
+///     // Levels must be descending
+///     const SERVICE: Database = 1 > Cache = 2;
+/// }
+/// ```
+#[macro_export]
+macro_rules! lock_hierarchy {
+    (
+        $(#[$meta:meta])*
+        $vis:vis const $hierarchy:ident:
+        $($(#[$level_meta:meta])* $level:ident $(= $value:literal)?)>+;
+    ) => {
+        $crate::lock_hierarchy!(@levels [$vis] $($(#[$level_meta])* $level $(= $value)?)>+);
+        $crate::lock_after!($($level)=>+);
+        $(#[$meta])*
+        $vis const $hierarchy: $crate::Hierarchy = $crate::Hierarchy::new(&[$((
+            stringify!($level),
+            <$level as $crate::typed::Level>::LEVEL,
+        )),+]);
+    };
+    (
+        @levels [$($vis:tt)*]
+        $(#[$meta:meta])* $level:ident $(= $value:literal)?
+        > $(#[$next_meta:meta])* $next:ident $($rest:tt)*
+    ) => {
+        $crate::lock_hierarchy!(
+            @level [$($vis)*] $(#[$meta])* $level
+            [$($value)?]
+            [<$next as $crate::typed::Level>::LEVEL + $crate::Hierarchy::GAP]
+        );
+        $crate::lock_hierarchy!(@levels [$($vis)*] $(#[$next_meta])* $next $($rest)*);
+    };
+    (@levels [$($vis:tt)*] $(#[$meta:meta])* $level:ident $(= $value:literal)?) => {
+        $crate::lock_hierarchy!(@level [$($vis)*] $(#[$meta])* $level [$($value)?] [0]);
+    };
+    (@level [$($vis:tt)*] $(#[$meta:meta])* $level:ident [$value:literal] [$default:expr]) => {
+        $crate::lock_hierarchy!(@level [$($vis)*] $(#[$meta])* $level [] [$value]);
+    };
+    (@level [$($vis:tt)*] $(#[$meta:meta])* $level:ident [] [$value:expr]) => {
+        $(#[$meta])*
+        #[derive(Debug)]
+        $($vis)* enum $level {}
+
+        impl $crate::typed::Level for $level {
+            const LEVEL: u32 = $value;
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        typed::{self, Level, LockToken},
+        Hierarchy,
+    };
+
+    lock_hierarchy! {
+        const SERVICE: Database > Cache > Metrics;
+    }
+
+    lock_hierarchy! {
+        const SINGLE: Only = 7;
+    }
+
+    #[test]
+    fn levels_are_spaced_by_gap() {
+        assert_eq!(2 * Hierarchy::GAP, Database::LEVEL);
+        assert_eq!(Hierarchy::GAP, Cache::LEVEL);
+        assert_eq!(0, Metrics::LEVEL);
+        assert_eq!(Some(Cache::LEVEL), SERVICE.level("Cache"));
+        assert_eq!(None, SERVICE.level("Unknown"));
+        assert_eq!(7, SINGLE.levels()[0].1);
+    }
+
+    #[test]
+    fn levels_are_ordered_for_typed_locks() {
+        let database = typed::Mutex::<_, Database>::new(());
+        let metrics = typed::Mutex::<_, Metrics>::new(());
+
+        // SAFETY: The test holds no locks yet
+        let mut token = unsafe { LockToken::unlocked() };
+        let (_database, mut token) = database.lock(&mut token).unwrap();
+        let _metrics = metrics.lock(&mut token).unwrap();
+    }
+}
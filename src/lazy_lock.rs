@@ -0,0 +1,151 @@
+use std::{
+    fmt::{Debug, Formatter},
+    ops::Deref,
+    sync::PoisonError,
+};
+
+use crate::OnceLock;
+
+/// Wrapper around a [`std::sync::LazyLock`] which treats running the initializer as acquiring a
+/// lock with its level.
+///
+/// See the [crate level documentation](crate) for more general information.
+///
+/// ```
+/// use lock_hierarchy::{LazyLock, Mutex};
+///
+/// let mutex = Mutex::new(42); // Level 0
+/// let answer = LazyLock::with_level(|| *mutex.lock().unwrap(), 1);
+///
+/// // Fine: 0 is lower level than 1
+/// assert_eq!(42, *answer);
+/// ```
+pub struct LazyLock<T, F = fn() -> T> {
+    once: OnceLock<T>,
+    /// Taken by the thread running the initialization. The mutex is never contended, since
+    /// `once` ensures the initializer runs at most once at a time.
+    init: std::sync::Mutex<Option<F>>,
+}
+
+impl<T, F: FnOnce() -> T> LazyLock<T, F> {
+    /// Creates a lazy value with level 0. Forcing it while holding any other lock causes an error
+    /// in debug builds, unless it is already initialized.
+    pub const fn new(f: F) -> Self {
+        Self::with_level(f, 0)
+    }
+
+    /// Creates a lazy value and assigns it a level in the lock hierarchy. Locks acquired by the
+    /// initializer must have a lower level.
+    ///
+    /// Takes a `u32` only, so it can be used in statics. Custom [`LockLevel`](crate::LockLevel)s,
+    /// e.g. enums, are not supported.
+    pub const fn with_level(f: F, level: u32) -> Self {
+        LazyLock {
+            once: OnceLock::with_level(level),
+            init: std::sync::Mutex::new(Some(f)),
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the lazy value. The name is part of every
+    /// violation report involving its initialization.
+    ///
+    /// Takes a `u32` level only, like [`Self::with_level`].
+    pub const fn with_name_and_level(f: F, name: &'static str, level: u32) -> Self {
+        LazyLock {
+            once: OnceLock::with_name_and_level(name, level),
+            init: std::sync::Mutex::new(Some(f)),
+        }
+    }
+
+    /// See [std::sync::LazyLock::force]
+    #[track_caller]
+    pub fn force(this: &Self) -> &T {
+        this.once.get_or_init(|| {
+            let init = this
+                .init
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            match init {
+                Some(f) => f(),
+                None => panic!("LazyLock instance has previously been poisoned"),
+            }
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for LazyLock<T, F> {
+    type Target = T;
+
+    #[track_caller]
+    fn deref(&self) -> &T {
+        LazyLock::force(self)
+    }
+}
+
+impl<T: Default> Default for LazyLock<T> {
+    /// Creates a new lazy value with level 0 using `Default` as the initializing function.
+    fn default() -> Self {
+        LazyLock::new(T::default)
+    }
+}
+
+impl<T: Debug, F> Debug for LazyLock<T, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_tuple("LazyLock");
+        match self.once.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::Mutex;
+
+    #[test]
+    fn initialize_on_first_access() {
+        let lazy = LazyLock::new(|| 42);
+        assert_eq!("LazyLock(<uninit>)", format!("{lazy:?}"));
+        assert_eq!(42, *lazy);
+        assert_eq!("LazyLock(42)", format!("{lazy:?}"));
+    }
+
+    #[test]
+    fn initialized_value_is_not_checked() {
+        let lazy = LazyLock::new(|| 42);
+        let mutex = Mutex::new(());
+        LazyLock::force(&lazy);
+        let _guard = mutex.lock().unwrap();
+        // Fine, the value is already initialized
+        assert_eq!(42, *lazy);
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn initialization_is_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let lazy = LazyLock::new(|| 42);
+                let mutex = Mutex::new(());
+                let _guard = mutex.lock().unwrap();
+                // Must panic, initialization counts as acquiring level 0
+                LazyLock::force(&lazy);
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "LazyLock instance has previously been poisoned")]
+    fn poisoned() {
+        let lazy = LazyLock::new(|| -> u32 { panic!("initialization failed") });
+        std::panic::catch_unwind(|| *lazy).unwrap_err();
+        LazyLock::force(&lazy);
+    }
+}
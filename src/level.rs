@@ -1,7 +1,27 @@
-#[cfg(debug_assertions)]
-use std::{cell::RefCell, thread_local};
+#[cfg(feature = "backtrace")]
+use std::sync::Arc;
+use std::{
+    backtrace::Backtrace,
+    fmt::{Debug, Display, Formatter},
+    marker::PhantomData,
+    panic::Location,
+};
+#[cfg(lock_check)]
+use std::{
+    cell::RefCell,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        PoisonError,
+    },
+    thread_local,
+};
 
-#[cfg(debug_assertions)]
+#[cfg(lock_check)]
+use crate::{blocking, graph, graph::check_mode, violation::report};
+use crate::{violation::HierarchyViolation, AnyLevel, LockLevel};
+
+#[cfg(lock_check)]
 thread_local! {
     /// We hold a stack of thread local lock levels.
     ///
@@ -10,17 +30,169 @@ thread_local! {
     /// * Stack: Just holding the current lock level would be insufficient in situations there locks
     ///   are released in a different order, from what they were acquired in. This way we can
     ///   support scenarios like e.g.: Acquire A, Acquire B, Release A, Acquire C, ...
+    ///   Locks acquired without a check (e.g. by `try_lock`) may be out of order, so the stack is
+    ///   not necessarily sorted. New acquisitions are checked against its lowest level.
     /// * RefCell: Static implies immutability in safe code, yet we want to mutate it. So we use a
     ///   `RefCell` to acquire interior mutability.
-    static LOCK_LEVELS: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+    /// * LockInfo: Besides the level, we keep what we know about each lock, so violations can be
+    ///   reported in detail.
+    static LOCK_LEVELS: LockLevels = const { LockLevels(RefCell::new(Vec::new())) };
+}
+
+/// The stack of [`LOCK_LEVELS`]. Cleans up after its entries, once the thread exits.
+#[cfg(lock_check)]
+struct LockLevels(RefCell<Vec<LockInfo>>);
+
+#[cfg(lock_check)]
+impl Deref for LockLevels {
+    type Target = RefCell<Vec<LockInfo>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(lock_check)]
+impl Drop for LockLevels {
+    fn drop(&mut self) {
+        // Nobody else will remove the acquisitions of this thread released elsewhere
+        remove_released_elsewhere(self.0.get_mut());
+    }
+}
+
+/// Describes a lock held by a thread, or a lock a thread tries to acquire.
+#[derive(Debug, Clone)]
+pub struct LockInfo {
+    pub(crate) level: AnyLevel,
+    name: Option<&'static str>,
+    type_name: Option<&'static str>,
+    location: &'static Location<'static>,
+    /// See [`Self::instance`].
+    instance: usize,
+    /// Acquired for shared access, e.g. by a read lock.
+    shared: bool,
+    /// Locks acquired together by [`lock_all`](crate::lock_all), or shards of the same
+    /// [`ShardedMutex`](crate::ShardedMutex), share a group. Siblings with the same level do not
+    /// violate the hierarchy, as long as they are acquired in the order of their instances. Zero
+    /// if the lock has been acquired on its own.
+    #[cfg(lock_check)]
+    group: usize,
+    /// Still waiting for the lock, e.g. by a pending future of an asynchronous lock. The level
+    /// is acquired before waiting, so the hierarchy is checked before a deadlock can happen.
+    #[cfg(lock_check)]
+    acquiring: bool,
+    /// Identifies this acquisition, e.g. so a guard of an asynchronous lock can find its entry.
+    #[cfg(lock_check)]
+    acquisition: usize,
+    /// Shared, since backtraces can not be cloned.
+    #[cfg(feature = "backtrace")]
+    backtrace: Arc<Backtrace>,
+}
+
+impl LockInfo {
+    /// Level of the lock in the hierarchy.
+    pub fn level(&self) -> &AnyLevel {
+        &self.level
+    }
+
+    /// Name of the lock, if it has been given one, e.g. by
+    /// [`Mutex::with_name_and_level`](crate::Mutex::with_name_and_level).
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Name of the type protected by the lock, as given by [`std::any::type_name`]. `None` for
+    /// locks which do not protect a value, e.g. [`Once`](crate::Once).
+    pub fn type_name(&self) -> Option<&'static str> {
+        self.type_name
+    }
+
+    /// Source location the lock has been acquired at, e.g. the call to
+    /// [`Mutex::lock`](crate::Mutex::lock).
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Whether the lock has been acquired for shared access, e.g. by
+    /// [`RwLock::read`](crate::RwLock::read).
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Identifies the lock instance. Entries with the same instance refer to the same lock. Once
+    /// a lock is dropped, its instance may be reused.
+    pub fn instance(&self) -> usize {
+        self.instance
+    }
+
+    /// Backtrace of the acquisition. Only captured if the `backtrace` feature is enabled.
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        #[cfg(feature = "backtrace")]
+        {
+            Some(&self.backtrace)
+        }
+        #[cfg(not(feature = "backtrace"))]
+        None
+    }
+}
+
+impl Display for LockInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "level {}", self.level)?;
+        if let Some(name) = self.name {
+            write!(f, " {name:?}")?;
+        }
+        if let Some(type_name) = self.type_name {
+            write!(f, " ({type_name})")?;
+        }
+        write!(f, " at {}", self.location)
+    }
 }
 
-#[derive(Debug)]
 pub(crate) struct Level {
     /// Level of this mutex in the hierarchy. Higher levels must be acquired first if locks are to
     /// be held simultaneously.
-    #[cfg(debug_assertions)]
-    pub(crate) level: u32,
+    #[cfg(lock_check)]
+    pub(crate) level: AnyLevel,
+    /// Name of the lock, only used for diagnostics.
+    #[cfg(lock_check)]
+    pub(crate) name: Option<&'static str>,
+    /// Yields the name of the protected type. [`std::any::type_name`] is not a `const fn`, so we
+    /// keep a pointer to it, in order to allow for `const` constructors.
+    #[cfg(lock_check)]
+    type_name: Option<fn() -> &'static str>,
+    /// Identifies the lock, see [`LockInfo::instance`]. Zero until it is first needed, in order
+    /// to allow for `const` constructors.
+    #[cfg(lock_check)]
+    instance: AtomicUsize,
+}
+
+/// Source of [`LockInfo::instance`].
+#[cfg(lock_check)]
+static NEXT_INSTANCE: AtomicUsize = AtomicUsize::new(1);
+/// Source of `LockInfo::group`.
+#[cfg(lock_check)]
+static NEXT_GROUP: AtomicUsize = AtomicUsize::new(1);
+/// Source of `LockInfo::acquisition`.
+#[cfg(lock_check)]
+static NEXT_ACQUISITION: AtomicUsize = AtomicUsize::new(1);
+
+/// Acquisitions whose [`TaskLevelGuard`] has been dropped by another task or thread than the one
+/// holding the level. Their entries are removed by the holder, once it touches its levels again,
+/// or once the thread exits or the task is dropped.
+#[cfg(lock_check)]
+static RELEASED_ELSEWHERE: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(Vec::new());
+/// Length of [`RELEASED_ELSEWHERE`], so the common case of an empty list does not need to lock it.
+#[cfg(lock_check)]
+static RELEASED_ELSEWHERE_LEN: AtomicUsize = AtomicUsize::new(0);
+
+impl Debug for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("Level");
+        #[cfg(lock_check)]
+        f.field("level", &self.level).field("name", &self.name);
+        f.finish()
+    }
 }
 
 impl Default for Level {
@@ -32,115 +204,789 @@ impl Default for Level {
 
 impl Level {
     #[inline]
-    pub fn new(level: u32) -> Self {
-        #[cfg(not(debug_assertions))]
+    pub const fn new(level: u32) -> Self {
+        #[cfg(not(lock_check))]
         let _ = level;
         Self {
-            #[cfg(debug_assertions)]
+            #[cfg(lock_check)]
+            level: AnyLevel::from_u32(level),
+            #[cfg(lock_check)]
+            name: None,
+            #[cfg(lock_check)]
+            type_name: None,
+            #[cfg(lock_check)]
+            instance: AtomicUsize::new(0),
+        }
+    }
+
+    /// Like [`Self::new`], for levels of any type.
+    #[inline]
+    pub fn of(level: impl LockLevel) -> Self {
+        #[cfg(lock_check)]
+        {
+            let mut this = Self::new(0);
+            this.level = AnyLevel::new(level);
+            this
+        }
+        #[cfg(not(lock_check))]
+        {
+            let _ = level;
+            Self::new(0)
+        }
+    }
+
+    /// Attaches a name to the lock, which is shown in diagnostics.
+    #[inline]
+    pub const fn named(self, name: &'static str) -> Self {
+        #[cfg(lock_check)]
+        {
+            let mut level = self;
+            level.name = Some(name);
+            level
+        }
+        #[cfg(not(lock_check))]
+        {
+            let _ = name;
+            self
+        }
+    }
+
+    /// Attaches the name of the type protected by the lock, which is shown in diagnostics.
+    #[inline]
+    #[cfg_attr(not(lock_check), allow(clippy::extra_unused_type_parameters))]
+    pub const fn protecting<T: ?Sized>(self) -> Self {
+        #[cfg(lock_check)]
+        {
+            let mut level = self;
+            level.type_name = Some(std::any::type_name::<T>);
+            level
+        }
+        #[cfg(not(lock_check))]
+        self
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn lock(&self) -> LevelGuard<'_> {
+        self.lock_at(Location::caller())
+    }
+
+    /// Like [`Self::lock`], for callers which can not pass on their location implicitly, e.g.
+    /// futures which acquire the level once polled.
+    #[inline]
+    pub fn lock_at(&self, location: &'static Location<'static>) -> LevelGuard<'_> {
+        #[cfg(lock_check)]
+        {
+            self.lock_in_group(location, 0)
+        }
+        #[cfg(not(lock_check))]
+        {
+            let _ = location;
+            LevelGuard::new(self)
+        }
+    }
+
+    /// Like [`Self::lock_at`], but locks of the same `group` (see [`Self::group`]) with the same
+    /// level do not violate the hierarchy, if acquired in the order of their instances.
+    #[cfg(lock_check)]
+    pub(crate) fn lock_in_group(
+        &self,
+        location: &'static Location<'static>,
+        group: usize,
+    ) -> LevelGuard<'_> {
+        let info = LockInfo {
+            group,
+            ..self.info(location)
+        };
+        check(&info);
+        // The violation handler may choose to continue, so we track the level either way
+        push(info);
+        LevelGuard::new(self)
+    }
+
+    /// A new group for [`Self::lock_in_group`].
+    #[cfg(lock_check)]
+    pub(crate) fn group() -> usize {
+        NEXT_GROUP.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Order in which to acquire several locks at once, as indices into `levels`: Higher levels
+    /// first, the same levels ordered by instance.
+    #[cfg(lock_check)]
+    pub(crate) fn acquisition_order(levels: &[&Level]) -> Vec<usize> {
+        let mut order: Vec<_> = (0..levels.len()).collect();
+        order.sort_by(|&a, &b| {
+            let (a, b) = (levels[a], levels[b]);
+            b.level
+                .total_cmp(&a.level)
+                .then_with(|| a.instance().cmp(&b.instance()))
+        });
+        order
+    }
+
+    /// Like [`Self::lock`], but returns the violation instead of reporting it to the violation
+    /// handler. The level is not acquired in that case.
+    #[inline]
+    #[track_caller]
+    pub fn checked_lock(&self) -> Result<LevelGuard<'_>, HierarchyViolation> {
+        #[cfg(lock_check)]
+        {
+            let info = self.info(Location::caller());
+            if let Some(violation) = violation(&info) {
+                return Err(violation);
+            }
+            push(info);
+        }
+        Ok(LevelGuard::new(self))
+    }
+
+    /// Pushes the level onto the thread local stack without checking it against the locks already
+    /// held. Used for non blocking acquisitions, which can not deadlock. Later acquisitions are
+    /// still checked against this level.
+    #[inline]
+    #[track_caller]
+    pub fn lock_unchecked(&self) -> LevelGuard<'_> {
+        #[cfg(lock_check)]
+        push(self.info(Location::caller()));
+        LevelGuard::new(self)
+    }
+
+    /// Like [`Self::lock_unchecked`], as a sibling of `group`, see [`Self::lock_in_group`].
+    #[cfg(lock_check)]
+    pub(crate) fn lock_unchecked_in_group(
+        &self,
+        location: &'static Location<'static>,
+        group: usize,
+    ) -> LevelGuard<'_> {
+        push(LockInfo {
+            group,
+            ..self.info(location)
+        });
+        LevelGuard::new(self)
+    }
+
+    /// Like [`Self::lock`], but the level is held until [`Self::release`] is called, rather than
+    /// until a guard is dropped. For locks whose acquisitions are not tied to a single guard.
+    #[inline]
+    #[track_caller]
+    pub fn acquire(&self) {
+        #[cfg(lock_check)]
+        {
+            let info = self.info(Location::caller());
+            check(&info);
+            // The violation handler may choose to continue, so we track the level either way
+            push(info);
+        }
+    }
+
+    /// Like [`Self::acquire`], but without checking the level, see [`Self::lock_unchecked`].
+    #[inline]
+    #[track_caller]
+    pub fn acquire_unchecked(&self) {
+        #[cfg(lock_check)]
+        push(self.info(Location::caller()));
+    }
+
+    /// Releases a level acquired by [`Self::acquire`].
+    #[inline]
+    pub fn release(&self) {
+        #[cfg(lock_check)]
+        self.remove();
+    }
+
+    #[cfg(lock_check)]
+    pub(crate) fn info(&self, location: &'static Location<'static>) -> LockInfo {
+        LockInfo {
+            level: self.level.clone(),
+            name: self.name,
+            type_name: self.type_name.map(|type_name| type_name()),
+            location,
+            instance: self.instance(),
+            shared: false,
+            group: 0,
+            acquiring: false,
+            acquisition: NEXT_ACQUISITION.fetch_add(1, Ordering::Relaxed),
+            #[cfg(feature = "backtrace")]
+            backtrace: Arc::new(Backtrace::force_capture()),
+        }
+    }
+
+    /// Identifies this lock, see [`LockInfo::instance`].
+    #[cfg(lock_check)]
+    pub(crate) fn instance(&self) -> usize {
+        match self.instance.load(Ordering::Relaxed) {
+            0 => {
+                let instance = NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed);
+                // Another thread may have been faster
+                match self.instance.compare_exchange(
+                    0,
+                    instance,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => instance,
+                    Err(instance) => instance,
+                }
+            }
+            instance => instance,
+        }
+    }
+
+    /// Whether the current thread holds this lock, for shared or exclusive access.
+    #[inline]
+    pub fn is_held(&self, shared: bool) -> bool {
+        #[cfg(lock_check)]
+        {
+            LOCK_LEVELS.with(|levels| {
+                levels
+                    .borrow()
+                    .iter()
+                    .any(|held| held.instance == self.instance() && held.shared == shared)
+            })
+        }
+        #[cfg(not(lock_check))]
+        {
+            let _ = shared;
+            false
+        }
+    }
+
+    /// Panics if the current thread does not hold this lock for the given access. Does nothing if
+    /// lock hierarchies are not checked.
+    #[inline]
+    #[track_caller]
+    pub fn assert_held(&self, shared: bool) {
+        #[cfg(lock_check)]
+        if !self.is_held(shared) {
+            let access = if shared { "shared" } else { "exclusive" };
+            match self.name {
+                Some(name) => panic!(
+                    "Lock {name:?} with level {} is not held by the current thread for {access} \
+                    access.",
+                    self.level
+                ),
+                None => panic!(
+                    "Lock with level {} is not held by the current thread for {access} access.",
+                    self.level
+                ),
+            }
+        }
+        #[cfg(not(lock_check))]
+        let _ = shared;
+    }
+
+    /// Index of the most recent entry of this lock on the thread local stack.
+    #[cfg(lock_check)]
+    fn position(&self, levels: &[LockInfo]) -> usize {
+        levels
+            .iter()
+            .rposition(|held| held.instance == self.instance())
+            .expect("Position must exist, because we inserted it during lock!")
+    }
+
+    /// Removes the most recent entry of this lock and returns it.
+    #[cfg(lock_check)]
+    fn remove(&self) -> LockInfo {
+        LOCK_LEVELS.with(|levels| {
+            let mut levels = levels.borrow_mut();
+            let index = self.position(&levels);
+            levels.remove(index)
+        })
+    }
+}
+
+/// Reports a violation if the level is not lower than the levels already held.
+#[cfg(lock_check)]
+fn check(attempted: &LockInfo) {
+    // Do not hold the borrow while reporting, the handler may inspect or acquire locks
+    if let Some(violation) = violation(attempted) {
+        report(violation)
+    }
+}
+
+/// The violation acquiring a lock would cause, if any. Records the acquisition in the lock order
+/// graph, if enabled.
+#[cfg(lock_check)]
+fn violation(attempted: &LockInfo) -> Option<HierarchyViolation> {
+    let mode = check_mode();
+    LOCK_LEVELS
+        .with(|levels| {
+            let mut levels = levels.borrow_mut();
+            remove_released_elsewhere(&mut levels);
+            (mode.checks_levels() && levels.iter().any(|held| held.conflicts_with(attempted)))
+                .then(|| HierarchyViolation::lock_order(attempted.clone(), levels.clone()))
+        })
+        .or_else(|| blocking::acquisition_violation(&attempted.level, || attempted.clone()))
+        .or_else(|| {
+            mode.records_graph()
+                .then(|| graph::acquisition_violation(attempted.clone()))
+                .flatten()
+        })
+}
+
+/// Removes the entries of acquisitions released by another task or thread from `levels`.
+#[cfg(lock_check)]
+fn remove_released_elsewhere(levels: &mut Vec<LockInfo>) {
+    if RELEASED_ELSEWHERE_LEN.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    // Only holds acquisitions, which are consistent after every statement
+    let mut released = RELEASED_ELSEWHERE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    levels.retain(
+        |held| match released.iter().position(|&a| a == held.acquisition) {
+            Some(index) => {
+                released.swap_remove(index);
+                false
+            }
+            None => true,
+        },
+    );
+    RELEASED_ELSEWHERE_LEN.store(released.len(), Ordering::Relaxed);
+}
+
+#[cfg(lock_check)]
+fn push(info: LockInfo) {
+    LOCK_LEVELS.with(|levels| levels.borrow_mut().push(info));
+}
+
+#[cfg(lock_check)]
+impl LockInfo {
+    /// Whether acquiring `attempted` while holding this lock violates the hierarchy. Levels of
+    /// different types can not be compared, so neither order of acquiring them is safe.
+    fn conflicts_with(&self, attempted: &LockInfo) -> bool {
+        // Siblings are acquired in the order of `Level::acquisition_order`
+        let siblings = self.group != 0
+            && self.group == attempted.group
+            && attempted
+                .level
+                .total_cmp(&self.level)
+                .then(self.instance.cmp(&attempted.instance))
+                .is_lt();
+        !attempted.level.is_lower_than(&self.level) && !siblings
+    }
+
+    /// Whether the thread or task still waits for the lock, see `LockInfo::acquiring`.
+    pub(crate) fn is_acquiring(&self) -> bool {
+        self.acquiring
+    }
+}
+
+#[cfg(lock_check)]
+impl Drop for Level {
+    fn drop(&mut self) {
+        let instance = *self.instance.get_mut();
+        if instance != 0 {
+            graph::remove(instance);
+        }
+    }
+}
+
+/// Snapshot of the locks currently held by this thread, in the order they have been acquired.
+///
+/// Always empty if lock hierarchies are not checked, e.g. in release builds.
+///
+/// ```
+/// use lock_hierarchy::{held_locks, Mutex};
+///
+/// let mutex = Mutex::with_name_and_level((), "config", 1);
+/// let _guard = mutex.lock().unwrap();
+/// # #[cfg(lock_check)]
+/// assert_eq!(Some("config"), held_locks()[0].name());
+/// ```
+pub fn held_locks() -> Vec<LockInfo> {
+    #[cfg(lock_check)]
+    {
+        LOCK_LEVELS.with(|levels| {
+            let mut levels = levels.borrow_mut();
+            remove_released_elsewhere(&mut levels);
+            levels.clone()
+        })
+    }
+    #[cfg(not(lock_check))]
+    Vec::new()
+}
+
+/// Number of locks currently held by this thread. Always `0` if lock hierarchies are not checked.
+pub fn held_lock_count() -> usize {
+    #[cfg(lock_check)]
+    {
+        LOCK_LEVELS.with(|levels| {
+            let mut levels = levels.borrow_mut();
+            remove_released_elsewhere(&mut levels);
+            levels.len()
+        })
+    }
+    #[cfg(not(lock_check))]
+    0
+}
+
+/// Whether this thread currently holds any lock. Always `false` if lock hierarchies are not
+/// checked.
+pub fn is_any_lock_held() -> bool {
+    held_lock_count() != 0
+}
+
+pub struct LevelGuard<'a> {
+    #[cfg(lock_check)]
+    pub(crate) level: &'a Level,
+    #[cfg(not(lock_check))]
+    _level: PhantomData<&'a Level>,
+}
+
+impl<'a> LevelGuard<'a> {
+    #[inline]
+    fn new(level: &'a Level) -> Self {
+        #[cfg(not(lock_check))]
+        let _ = level;
+        Self {
+            #[cfg(lock_check)]
             level,
+            #[cfg(not(lock_check))]
+            _level: PhantomData,
+        }
+    }
+
+    /// Marks the acquisition as shared, e.g. by a read lock. See [`LockInfo::is_shared`].
+    #[inline]
+    pub fn shared(self) -> Self {
+        self.access(true)
+    }
+
+    /// Marks the acquisition as exclusive again, e.g. once a read lock has been upgraded. The entry
+    /// keeps its place on the stack and is not checked again.
+    #[inline]
+    pub fn exclusive(self) -> Self {
+        self.access(false)
+    }
+
+    /// Turns this guard into one which may be dropped by any task or thread, e.g. for the guards
+    /// of asynchronous locks, which are `Send`.
+    #[inline]
+    pub fn into_task_guard(self) -> TaskLevelGuard<'a> {
+        #[cfg(lock_check)]
+        let acquisition = LOCK_LEVELS.with(|levels| {
+            let levels = levels.borrow();
+            levels[self.level.position(&levels)].acquisition
+        });
+        // The entry is removed by the task guard from now on
+        #[cfg(lock_check)]
+        std::mem::forget(self);
+        #[cfg(not(lock_check))]
+        let _ = self;
+        TaskLevelGuard {
+            #[cfg(lock_check)]
+            acquisition,
+            _level: PhantomData,
         }
     }
 
+    /// Marks the level as still waiting for the lock, or as acquired once the lock is. See
+    /// `LockInfo::acquiring`.
+    #[inline]
+    pub fn acquiring(self, acquiring: bool) -> Self {
+        #[cfg(lock_check)]
+        self.update(|info| info.acquiring = acquiring);
+        #[cfg(not(lock_check))]
+        let _ = acquiring;
+        self
+    }
+
     #[inline]
-    pub fn lock(&self) -> LevelGuard {
-        #[cfg(debug_assertions)]
+    fn access(self, shared: bool) -> Self {
+        #[cfg(lock_check)]
+        self.update(|info| info.shared = shared);
+        #[cfg(not(lock_check))]
+        let _ = shared;
+        self
+    }
+
+    /// Updates the entry of the level on the thread local stack.
+    #[cfg(lock_check)]
+    fn update(&self, f: impl FnOnce(&mut LockInfo)) {
         LOCK_LEVELS.with(|levels| {
             let mut levels = levels.borrow_mut();
-            if let Some(&lowest) = levels.last() {
-                if lowest <= self.level {
-                    panic!(
-                        "Tried to acquire lock with level {} while a lock with level {} \
-                        is acquired. This is a violation of lock hierarchies which \
-                        could lead to deadlocks.",
-                        self.level, lowest
-                    )
+            let index = self.level.position(&levels);
+            f(&mut levels[index]);
+        });
+    }
+
+    /// Removes the level from the thread local stack while `f` runs, e.g. while a thread is
+    /// parked on a condition variable. Afterwards the level is acquired again at the location of
+    /// the caller and checked against the locks held at that time.
+    #[inline]
+    #[track_caller]
+    pub fn released<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        self.released_at(Location::caller(), f)
+    }
+
+    /// Like [`Self::released`], for callers which can not pass on their location implicitly, e.g.
+    /// from within a closure.
+    #[inline]
+    pub fn released_at<R>(
+        &mut self,
+        location: &'static Location<'static>,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        #[cfg(lock_check)]
+        {
+            /// Puts the level back on the stack, even if `f` or the check panics. Otherwise
+            /// dropping the `LevelGuard` would not find its entry.
+            struct Reacquire(LockInfo);
+
+            impl Drop for Reacquire {
+                fn drop(&mut self) {
+                    push(self.0.clone());
                 }
             }
-            levels.push(self.level);
-        });
-        LevelGuard {
-            #[cfg(debug_assertions)]
-            level: self.level,
+
+            let released = self.level.remove();
+            let reacquire = Reacquire(LockInfo {
+                shared: released.shared,
+                group: released.group,
+                acquisition: released.acquisition,
+                ..self.level.info(location)
+            });
+            let result = f();
+            check(&reacquire.0);
+            drop(reacquire);
+            result
+        }
+        #[cfg(not(lock_check))]
+        {
+            let _ = location;
+            f()
         }
     }
 }
 
-pub struct LevelGuard {
-    #[cfg(debug_assertions)]
-    pub(crate) level: u32,
+#[cfg(lock_check)]
+impl Drop for LevelGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.level.remove();
+    }
 }
 
-#[cfg(debug_assertions)]
-impl Drop for LevelGuard {
+/// Level acquired by an asynchronous lock, see [`LevelGuard::into_task_guard`]. Unlike a
+/// [`LevelGuard`], it may be dropped by another task or thread than the one holding the level.
+pub struct TaskLevelGuard<'a> {
+    /// See `LockInfo::acquisition`.
+    #[cfg(lock_check)]
+    acquisition: usize,
+    _level: PhantomData<&'a Level>,
+}
+
+impl TaskLevelGuard<'_> {
+    /// Whether the level is on the stack of the current thread, including the levels of the task
+    /// it polls. Always `true` if lock hierarchies are not checked.
     #[inline]
-    fn drop(&mut self) {
+    pub fn is_tracked_here(&self) -> bool {
+        #[cfg(lock_check)]
+        {
+            LOCK_LEVELS.with(|levels| {
+                levels
+                    .borrow()
+                    .iter()
+                    .any(|held| held.acquisition == self.acquisition)
+            })
+        }
+        #[cfg(not(lock_check))]
+        true
+    }
+
+    /// See [`LevelGuard::acquiring`]. Does nothing if the level is not tracked here, see
+    /// [`Self::is_tracked_here`].
+    #[inline]
+    pub fn acquiring(self, acquiring: bool) -> Self {
+        #[cfg(lock_check)]
         LOCK_LEVELS.with(|levels| {
+            let mut levels = levels.borrow_mut();
+            if let Some(held) = levels
+                .iter_mut()
+                .rfind(|held| held.acquisition == self.acquisition)
+            {
+                held.acquiring = acquiring;
+            }
+        });
+        #[cfg(not(lock_check))]
+        let _ = acquiring;
+        self
+    }
+}
+
+#[cfg(lock_check)]
+impl Drop for TaskLevelGuard<'_> {
+    fn drop(&mut self) {
+        let removed = LOCK_LEVELS.with(|levels| {
             let mut levels = levels.borrow_mut();
             let index = levels
                 .iter()
-                .rposition(|&level| level == self.level)
-                .expect("Position must exist, because we inserted it during lock!");
-            levels.remove(index);
+                .rposition(|held| held.acquisition == self.acquisition)?;
+            Some(levels.remove(index))
         });
+        if removed.is_none() {
+            // Held by a suspended task, or by another thread
+            let mut released = RELEASED_ELSEWHERE
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            released.push(self.acquisition);
+            RELEASED_ELSEWHERE_LEN.store(released.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Levels held by an asynchronous task. A task may be polled by different threads, so its levels
+/// are kept here between polls, rather than on the stack of any thread.
+#[derive(Debug, Default)]
+pub(crate) struct TaskLevels {
+    #[cfg(lock_check)]
+    levels: Vec<LockInfo>,
+}
+
+#[cfg(lock_check)]
+impl Drop for TaskLevels {
+    fn drop(&mut self) {
+        // Nobody else will remove the acquisitions of this task released elsewhere
+        remove_released_elsewhere(&mut self.levels);
+    }
+}
+
+impl TaskLevels {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(lock_check)]
+            levels: Vec::new(),
+        }
+    }
+
+    /// Puts the levels of the task on top of the thread local stack while `f` runs, e.g. while
+    /// the task is polled. Afterwards all levels on the stack, which the thread did not hold
+    /// before, belong to the task again. This way locks held by the polling thread are still
+    /// checked, but do not become part of the task.
+    #[inline]
+    pub fn enter<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        #[cfg(lock_check)]
+        {
+            /// Moves the levels of the task back, even if `f` panics.
+            struct Leave<'a> {
+                levels: &'a mut Vec<LockInfo>,
+                /// Acquisitions held by the thread before. The task may release some of them, so
+                /// their number does not tell where the levels of the task start.
+                outer: Vec<usize>,
+            }
+
+            impl Drop for Leave<'_> {
+                fn drop(&mut self) {
+                    LOCK_LEVELS.with(|levels| {
+                        let mut levels = levels.borrow_mut();
+                        let (outer, task) = levels
+                            .drain(..)
+                            .partition(|held| self.outer.contains(&held.acquisition));
+                        *levels = outer;
+                        *self.levels = task;
+                    });
+                    remove_released_elsewhere(self.levels);
+                }
+            }
+
+            remove_released_elsewhere(&mut self.levels);
+            let outer = LOCK_LEVELS.with(|levels| {
+                let mut levels = levels.borrow_mut();
+                let outer = levels.iter().map(|held| held.acquisition).collect();
+                levels.append(&mut self.levels);
+                outer
+            });
+            let _leave = Leave {
+                levels: &mut self.levels,
+                outer,
+            };
+            f()
+        }
+        #[cfg(not(lock_check))]
+        f()
+    }
+
+    /// Levels held by the task while it is not polled.
+    #[cfg(lock_check)]
+    pub fn held(&self) -> &[LockInfo] {
+        &self.levels
+    }
+
+    /// Moves the levels still held by the task onto the thread local stack. Used once the task
+    /// completes, since guards returned by it are now owned by the polling thread.
+    #[inline]
+    pub fn hand_over(&mut self) {
+        #[cfg(lock_check)]
+        LOCK_LEVELS.with(|levels| levels.borrow_mut().append(&mut self.levels));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
 
     #[test]
-    #[cfg(debug_assertions)]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
+    #[cfg(lock_check)]
     fn self_deadlock_detected() {
-        let mutex = Level::new(0);
-        let _guard_a = mutex.lock();
-        // This must panic
-        let _guard_b = mutex.lock();
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Level::new(0);
+                let _guard_a = mutex.lock();
+                // This must panic
+                let _guard_b = mutex.lock();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[cfg(debug_assertions)]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
+    #[cfg(lock_check)]
     fn panic_if_two_mutexes_with_level_0_are_acquired() {
-        let mutex_a = Level::new(0);
-        let mutex_b = Level::new(0);
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Level::new(0);
+                let mutex_b = Level::new(0);
 
-        // Fine, first mutex in thread
-        let _guard_a = mutex_a.lock();
-        // Must panic, lock hierarchy violation
-        let _guard_b = mutex_b.lock();
+                // Fine, first mutex in thread
+                let _guard_a = mutex_a.lock();
+                // Must panic, lock hierarchy violation
+                let _guard_b = mutex_b.lock();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn created_by_default_impl_should_be_level_0() {
         // This test would fail if mutex_a had any level greater than 0.
         let mutex = Level::default();
-        assert_eq!(mutex.level, 0);
+        assert_eq!(Some(0), mutex.level.as_integer());
     }
 
     #[test]
-    #[cfg(debug_assertions)]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 1 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
+    #[cfg(lock_check)]
     fn panic_if_0_is_acquired_before_1() {
-        let mutex_a = Level::new(0); // Level 0
-        let mutex_b = Level::new(1); // Level 1
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Level::new(0); // Level 0
+                let mutex_b = Level::new(1); // Level 1
 
-        // Fine, first mutex in thread
-        let _guard_a = mutex_a.lock();
-        // Must panic, lock hierarchy violation
-        let _guard_b = mutex_b.lock();
+                // Fine, first mutex in thread
+                let _guard_a = mutex_a.lock();
+                // Must panic, lock hierarchy violation
+                let _guard_b = mutex_b.lock();
+            }),
+            (Some(1), vec![0])
+        );
     }
 
     #[test]
-    #[cfg(not(debug_assertions))]
+    #[cfg(not(lock_check))]
     fn should_not_check_in_release_build() {
         let mutex_a = Level::new(0);
         let mutex_b = Level::new(0);
@@ -188,4 +1034,163 @@ mod tests {
         #[allow(clippy::drop_non_drop)]
         drop(guard_b)
     }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn task_levels_are_kept_between_polls() {
+        let outer = Level::new(2);
+        let mutex = Level::new(1);
+        let mut task = TaskLevels::new();
+
+        let _outer_guard = outer.lock();
+        let guard = task.enter(|| mutex.lock());
+        // Level 1 is held by the task, not by the thread
+        let levels: Vec<_> = held_locks()
+            .iter()
+            .map(|held| held.level().as_integer())
+            .collect();
+        assert_eq!(levels, [Some(2)]);
+        assert_eq!(Some(1), task.levels[0].level.as_integer());
+        task.enter(|| drop(guard));
+        assert!(task.levels.is_empty());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn released_elsewhere_is_purged_once_the_holder_exits() {
+        let level = Level::new(0);
+        let acquisition = std::thread::scope(|s| {
+            let (guard_sender, guard_receiver) = std::sync::mpsc::channel();
+            let (exit_sender, exit_receiver) = std::sync::mpsc::channel::<()>();
+            let level = &level;
+            let holder = s.spawn(move || {
+                guard_sender.send(level.lock().into_task_guard()).unwrap();
+                exit_receiver.recv().unwrap_err();
+            });
+            let guard = guard_receiver.recv().unwrap();
+            let acquisition = guard.acquisition;
+            // Not held by this thread, so the holder has to remove the entry
+            drop(guard);
+            assert!(released_elsewhere().contains(&acquisition));
+            drop(exit_sender);
+            holder.join().unwrap();
+            acquisition
+        });
+        assert!(!released_elsewhere().contains(&acquisition));
+    }
+
+    #[cfg(lock_check)]
+    fn released_elsewhere() -> Vec<usize> {
+        RELEASED_ELSEWHERE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn held_locks_are_reported() {
+        let mutex_a = Level::new(1).named("a");
+        let mutex_b = Level::new(0);
+        assert!(!is_any_lock_held());
+
+        let _guard_a = mutex_a.lock();
+        let guard_b = mutex_b.lock();
+        let held = held_locks();
+        assert_eq!(2, held_lock_count());
+        assert_eq!(
+            (Some(1), Some("a")),
+            (held[0].level().as_integer(), held[0].name())
+        );
+        assert_eq!(
+            (Some(0), None),
+            (held[1].level().as_integer(), held[1].name())
+        );
+        drop(guard_b);
+        assert_eq!(1, held_lock_count());
+        assert!(is_any_lock_held());
+    }
+
+    #[test]
+    #[cfg(not(lock_check))]
+    fn nothing_is_held_without_checks() {
+        let mutex = Level::new(0);
+        let _guard = mutex.lock();
+        assert!(held_locks().is_empty());
+        assert!(!is_any_lock_held());
+    }
+
+    #[test]
+    fn released_level_is_acquired_again() {
+        let mutex_a = Level::new(1);
+        let mutex_b = Level::new(0);
+
+        let mut guard_a = mutex_a.lock();
+        // Fine, level 1 is not on the stack while released
+        guard_a.released(|| {
+            let _guard = mutex_a.lock();
+        });
+        // Fine: 0 is lower level than 1
+        let _guard_b = mutex_b.lock();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn released_level_is_checked_again() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Level::new(1);
+                let mutex_b = Level::new(0);
+
+                let mut guard_a = mutex_a.lock();
+                let mut guard_b = None;
+                // Must panic, level 0 is held once level 1 is acquired again
+                guard_a.released(|| guard_b = Some(mutex_b.lock()));
+            }),
+            (Some(1), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn checked_lock_returns_violation() {
+        let mutex_a = Level::new(0);
+        let mutex_b = Level::new(0);
+
+        let _guard_a = mutex_a.lock();
+        let violation = mutex_b.checked_lock().err().unwrap();
+        assert_eq!(Some(0), violation.attempted().unwrap().level().as_integer());
+        assert_eq!(1, violation.held().len());
+        // Fine, level 0 has not been pushed by the failed attempt
+        drop(_guard_a);
+        let _guard_b = mutex_b.lock();
+    }
+
+    #[test]
+    fn unchecked_lock_out_of_order() {
+        let mutex_a = Level::new(0);
+        let mutex_b = Level::new(1);
+
+        let _guard_a = mutex_a.lock();
+        // Fine, unchecked locks may violate the hierarchy
+        let _guard_b = mutex_b.lock_unchecked();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn check_against_lowest_held_level() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Level::new(1);
+                let mutex_b = Level::new(3);
+                let mutex_c = Level::new(2);
+
+                let _guard_a = mutex_a.lock();
+                let _guard_b = mutex_b.lock_unchecked();
+                // Must panic, level 1 is still held, even though level 3 has been pushed last
+                let _guard_c = mutex_c.lock();
+            }),
+            (Some(2), vec![1, 3])
+        );
+    }
 }
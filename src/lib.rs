@@ -1,18 +1,125 @@
 //! This crate offers debug assertions for violations of lock hierarchies. No runtime overhead or
 //! protection occurs for release builds.
 //!
+//! Whether hierarchies are checked can be overridden with cargo features:
+//!
+//! * `always-check`: Check in any build, e.g. for release mode integration or soak tests.
+//! * `never-check`: Never check, not even in debug builds, e.g. for benchmarks.
+//! * `backtrace`: Capture a backtrace for every checked acquisition. Violations print the
+//!   backtraces of the offending acquisition and of the conflicting held locks. See
+//!   [LockInfo::backtrace].
+//!
+//! If both `always-check` and `never-check` are enabled, `always-check` takes precedence.
+//!
 //! Each lock is assigned a level. Locks with higher levels must be acquired before locks with
 //! lower levels.
-//! Both [RwLock] and [Mutex] use the same hierarchy.
+//! Both [RwLock] and [Mutex] use the same hierarchy. [Condvar] releases the level of its mutex
+//! while waiting and checks it again once the mutex is reacquired. Upgrading the
+//! [UpgradableRwLock::upgradable_read] guard of an [UpgradableRwLock] or downgrading a write guard
+//! keeps the level held, without checking it again. [MutexGuard::unlocked] releases a mutex and its
+//! level while a closure runs and checks the level again before locking the mutex again. The guards
+//! of [RwLock] offer the same.
+//!
+//! Non blocking acquisitions ([Mutex::try_lock], [RwLock::try_read] and [RwLock::try_write]) can
+//! not deadlock and are therefore allowed out of order. Locks acquired this way still count as
+//! held, so later blocking acquisitions are checked against them.
+//!
+//! Lazy initialization hides a lock, too: A thread forcing a [LazyLock] blocks while another
+//! thread runs the initializer. [Once], [OnceLock] and [LazyLock] therefore have a level as well.
+//! Running the initialization counts as holding a lock with that level, so acquisitions within
+//! the initializer are checked against it. Accessing an already initialized value never blocks and
+//! is not checked.
+//!
+//! By default a violation panics with a [HierarchyViolation] as payload. The panic hook can not
+//! print such a payload, so the violation is printed to stderr right before panicking, even if the
+//! panic is caught later on. Use [set_violation_handler] to log, count or collect violations
+//! instead. Code which is able to back off can use the `checked_*` acquisitions (e.g.
+//! [Mutex::checked_lock]), which return the violation as an error instead.
+//!
+//! Earlier versions panicked with a message, rather than a [HierarchyViolation]. Tests using
+//! `#[should_panic(expected = "...")]` fail with such a payload. Catch the panic with
+//! [std::panic::catch_unwind] and downcast the payload instead, or collect the violations with
+//! [ViolationHandler::Collect].
+//!
+//! Assigning levels to every lock of a large code base is laborious. [set_check_mode] switches to
+//! (or adds) a lock order graph instead, which learns the order of locks at runtime and reports
+//! a violation once two locks are acquired in opposite orders. Locks created with [Mutex::new]
+//! participate just like locks with levels.
+//!
+//! Blocking work, e.g. I/O, while holding a lock stalls every thread waiting for it. Mark it with
+//! [assert_no_locks_held] or a [BlockingSection], in order to report a violation if locks are held.
+//!
+//! [held_locks] returns the locks held by the current thread, e.g. for logging or assertions in
+//! tests.
+//!
+//! Violations report the level and the acquisition site of every lock involved. Locks created with
+//! `with_name_and_level` (e.g. [Mutex::with_name_and_level]) are reported with their name and the
+//! name of the type they protect, too.
+//!
+//! Levels need not be numbers. [Mutex::with_level] accepts any [LockLevel], e.g. an enum of the
+//! layers of your application or a tuple of sub-levels, and violations show their names. Levels of
+//! different types can not be compared, so acquiring a lock while holding one with a level of
+//! another type is a violation.
+//!
+//! Locks with the same level may not be held at once, unless they are acquired together by
+//! [lock_all] or [lock!], which pick an order that can not deadlock. The shards of a
+//! [ShardedMutex] share a level, too, and may be held at once if locked in ascending order.
+//! A [ReentrantMutex] may be locked again by the thread holding it. Only its first acquisition is
+//! checked.
+//!
+//! Rather than scattering level numbers, declare the hierarchy in one place with
+//! [lock_hierarchy!], which computes the levels and can render the hierarchy as a table.
+//!
+//! Runtime checks only fire if the offending code path is executed. The [typed] module offers
+//! locks whose levels are types, so acquiring them out of order does not compile.
+//!
+//! Asynchronous tasks are not bound to a thread. The [future] module offers asynchronous locks
+//! which track the hierarchy per task.
 
+mod blocking;
+mod condvar;
+pub mod future;
+mod graph;
+mod hierarchy;
+mod lazy_lock;
 mod level;
+mod lock_all;
+mod lock_level;
 mod mutex;
+mod once;
+mod once_lock;
+mod reentrant_mutex;
 mod rwlock;
+mod sharded;
+pub mod typed;
+mod violation;
 
-use std::sync::{LockResult, PoisonError};
+use std::{
+    mem, process, ptr,
+    sync::{LockResult, PoisonError, TryLockError, TryLockResult},
+};
 
-pub use mutex::{Mutex, MutexGuard};
-pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use blocking::{assert_no_locks_held, BlockingSection};
+pub use condvar::{Condvar, WaitTimeoutResult};
+pub use graph::{check_mode, set_check_mode, CheckMode, ObservedOrder};
+pub use hierarchy::Hierarchy;
+pub use lazy_lock::LazyLock;
+pub use level::{held_lock_count, held_locks, is_any_lock_held, LockInfo};
+pub use lock_all::{lock_all, LockAll};
+pub use lock_level::{AnyLevel, LockLevel};
+pub use mutex::{MappedMutexGuard, Mutex, MutexGuard};
+pub use once::Once;
+pub use once_lock::OnceLock;
+pub use reentrant_mutex::{ReentrantMutex, ReentrantMutexGuard};
+pub use rwlock::{
+    MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock, RwLockReadGuard,
+    RwLockUpgradableReadGuard, RwLockWriteGuard, UpgradableRwLock, UpgradableRwLockWriteGuard,
+};
+pub use sharded::ShardedMutex;
+pub use violation::{
+    set_violation_handler, take_violations, violation_count, CheckedLockError, CheckedLockResult,
+    HierarchyViolation, ViolationHandler, ViolationKind,
+};
 
 pub(crate) fn map_guard<G, F>(result: LockResult<G>, f: impl FnOnce(G) -> F) -> LockResult<F> {
     match result {
@@ -20,3 +127,58 @@ pub(crate) fn map_guard<G, F>(result: LockResult<G>, f: impl FnOnce(G) -> F) ->
         Err(err) => Err(PoisonError::new(f(err.into_inner()))),
     }
 }
+
+pub(crate) fn map_try_guard<G, F>(
+    result: TryLockResult<G>,
+    f: impl FnOnce(G) -> F,
+) -> TryLockResult<F> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(TryLockError::Poisoned(err)) => Err(TryLockError::Poisoned(PoisonError::new(f(
+            err.into_inner()
+        )))),
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    }
+}
+
+/// Runs `f` with the lock held by `guard` released. Afterwards `lock` acquires it again and
+/// overwrites `guard`, even if `f` panics, since the owner of `guard` drops it either way. `lock`
+/// is expected to ignore poisoning, since there is no way to report it.
+///
+/// While `guard` is released, its owner must not drop it again. So if releasing or acquiring the
+/// lock panics, there is no guard to leave in its place and the process is aborted instead.
+pub(crate) fn unlocked<G, R>(guard: &mut G, lock: impl FnOnce() -> G, f: impl FnOnce() -> R) -> R {
+    struct Relock<'g, G, L: FnOnce() -> G> {
+        guard: &'g mut G,
+        lock: Option<L>,
+    }
+
+    impl<G, L: FnOnce() -> G> Drop for Relock<'_, G, L> {
+        fn drop(&mut self) {
+            let lock = self.lock.take().expect("Relock is dropped only once");
+            let abort = AbortOnUnwind;
+            // SAFETY: `guard` has been released already, so overwriting it does not leak a lock
+            unsafe { ptr::write(self.guard, lock()) }
+            mem::forget(abort);
+        }
+    }
+
+    /// Aborts the process if dropped, which only happens while unwinding.
+    struct AbortOnUnwind;
+
+    impl Drop for AbortOnUnwind {
+        fn drop(&mut self) {
+            process::abort()
+        }
+    }
+
+    let abort = AbortOnUnwind;
+    // SAFETY: The released `guard` is not used before `Relock` has overwritten it
+    drop(unsafe { ptr::read(guard) });
+    mem::forget(abort);
+    let _relock = Relock {
+        guard,
+        lock: Some(lock),
+    };
+    f()
+}
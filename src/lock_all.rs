@@ -0,0 +1,272 @@
+#[cfg(lock_check)]
+use std::panic::Location;
+use std::sync::{LockResult, PoisonError};
+#[cfg(not(lock_check))]
+use std::{sync::TryLockError, thread};
+
+#[cfg(lock_check)]
+use crate::level::Level;
+use crate::{Mutex, MutexGuard};
+
+/// Locks which can be acquired at once by [`lock_all`], i.e. tuples of up to six references to
+/// [`Mutex`]es.
+pub trait LockAll {
+    /// Guards of the locks, in the order the locks have been given.
+    type Guards;
+
+    /// See [`lock_all`].
+    #[track_caller]
+    fn lock_all(self) -> LockResult<Self::Guards>;
+}
+
+/// Acquires several locks at once, in an order which can not deadlock, and returns their guards
+/// in the order the locks have been given.
+///
+/// In debug builds the locks are acquired from the highest to the lowest level, locks with the
+/// same level in a stable order per lock. The first lock is checked against the locks already
+/// held as usual. Locks with the same level are registered as a group of siblings, so holding
+/// them at once is no violation. In builds without checks, levels are not known. Instead the first
+/// lock is acquired blocking and the others only if they are free. Otherwise all of them are
+/// released and the attempt starts over with the contended lock.
+///
+/// Poisoned locks are acquired nevertheless. If any of them is poisoned, all guards are returned
+/// within the [`PoisonError`]. See also [`lock!`](crate::lock).
+///
+/// Passing the same lock twice would deadlock. It is reported as a violation in debug builds. In
+/// builds without checks it panics.
+///
+/// ```
+/// use lock_hierarchy::{lock_all, Mutex};
+///
+/// let from = Mutex::with_level(100, 5);
+/// let to = Mutex::with_level(0, 5);
+///
+/// // Fine, both accounts are acquired at once
+/// let (mut from, mut to) = lock_all((&from, &to)).unwrap();
+/// *from -= 10;
+/// *to += 10;
+/// ```
+#[track_caller]
+pub fn lock_all<L: LockAll>(locks: L) -> LockResult<L::Guards> {
+    locks.lock_all()
+}
+
+/// Acquires several locks at once, like [`lock_all`].
+///
+/// ```
+/// use lock_hierarchy::{lock, Mutex};
+///
+/// let from = Mutex::with_level(100, 5);
+/// let to = Mutex::with_level(0, 5);
+///
+/// let (mut from, mut to) = lock!(from, to).unwrap();
+/// *from -= 10;
+/// *to += 10;
+/// ```
+#[macro_export]
+macro_rules! lock {
+    ($($lock:expr),+ $(,)?) => {
+        $crate::lock_all(($(&$lock,)+))
+    };
+}
+
+macro_rules! impl_lock_all {
+    ($($index:tt $t:ident),+) => {
+        impl<'a, $($t),+> LockAll for ($(&'a Mutex<$t>,)+) {
+            type Guards = ($(MutexGuard<'a, $t>,)+);
+
+            #[track_caller]
+            fn lock_all(self) -> LockResult<Self::Guards> {
+                #[cfg(lock_check)]
+                let guards = {
+                    let location = Location::caller();
+                    let group = Level::group();
+                    let mut guards = ($(None::<LockResult<MutexGuard<'a, $t>>>,)+);
+                    for index in Level::acquisition_order(&[$(&self.$index.level),+]) {
+                        match index {
+                            $($index => {
+                                guards.$index = Some(self.$index.lock_in_group(location, group))
+                            })+
+                            _ => unreachable!(),
+                        }
+                    }
+                    guards
+                };
+                #[cfg(not(lock_check))]
+                let guards = {
+                    // Otherwise the attempts below would wait for themselves forever
+                    let addresses = [$(self.$index as *const Mutex<$t> as *const ()),+];
+                    for (index, address) in addresses.iter().enumerate() {
+                        assert!(
+                            !addresses[..index].contains(address),
+                            "lock_all must not acquire the same lock twice, this would deadlock."
+                        );
+                    }
+                    let mut first = 0;
+                    loop {
+                        let mut guards = ($(None::<LockResult<MutexGuard<'a, $t>>>,)+);
+                        match first {
+                            $($index => guards.$index = Some(self.$index.lock()),)+
+                            _ => unreachable!(),
+                        }
+                        $(
+                            if guards.$index.is_none() {
+                                match self.$index.try_lock() {
+                                    Ok(guard) => guards.$index = Some(Ok(guard)),
+                                    Err(TryLockError::Poisoned(error)) => {
+                                        guards.$index = Some(Err(error))
+                                    }
+                                    Err(TryLockError::WouldBlock) => {
+                                        // Releases the guards acquired so far
+                                        first = $index;
+                                        thread::yield_now();
+                                        continue;
+                                    }
+                                }
+                            }
+                        )+
+                        break guards;
+                    }
+                };
+                let mut poisoned = false;
+                let guards = ($(
+                    guards.$index.expect("All locks have been acquired").unwrap_or_else(|error| {
+                        poisoned = true;
+                        error.into_inner()
+                    }),
+                )+);
+                if poisoned {
+                    Err(PoisonError::new(guards))
+                } else {
+                    Ok(guards)
+                }
+            }
+        }
+    };
+}
+
+impl_lock_all!(0 A);
+impl_lock_all!(0 A, 1 B);
+impl_lock_all!(0 A, 1 B, 2 C);
+impl_lock_all!(0 A, 1 B, 2 C, 3 D);
+impl_lock_all!(0 A, 1 B, 2 C, 3 D, 4 E);
+impl_lock_all!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+
+    #[test]
+    fn same_level_at_once() {
+        let a = Mutex::with_level(1, 5);
+        let b = Mutex::with_level(2, 5);
+        let c = Mutex::with_level(3, 4);
+
+        let (a, b, c) = lock_all((&a, &b, &c)).unwrap();
+        assert_eq!((1, 2, 3), (*a, *b, *c));
+    }
+
+    #[test]
+    fn levels_of_different_types_at_once() {
+        let integer = Mutex::with_level((), 5);
+        let tuple = Mutex::with_level((), (5u32, 0u32));
+
+        // Fine, neither order of acquisition can be checked, but all callers agree on one
+        let guards = lock!(integer, tuple).unwrap();
+        drop(guards);
+        let _guards = lock!(tuple, integer).unwrap();
+    }
+
+    #[test]
+    fn lower_level_after_group() {
+        let a = Mutex::with_level((), 5);
+        let b = Mutex::with_level((), 5);
+        let c = Mutex::with_level((), 4);
+
+        let _guards = lock!(a, b).unwrap();
+        // Fine, 4 is lower than the group
+        let _c = c.lock().unwrap();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn group_is_checked_against_held_locks() {
+        assert_eq!(
+            catch_violation(|| {
+                let a = Mutex::with_level((), 5);
+                let b = Mutex::with_level((), 5);
+                let c = Mutex::with_level((), 5);
+
+                let _c = c.lock().unwrap();
+                // Must panic, c is a sibling of neither a nor b
+                let _guards = lock!(a, b);
+            }),
+            (Some(5), vec![5])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn same_lock_twice_is_a_violation() {
+        assert_eq!(
+            catch_violation(|| {
+                let a = Mutex::with_level((), 5);
+                // Must panic, this would deadlock
+                let _guards = lock!(a, a);
+            }),
+            (Some(5), vec![5])
+        );
+    }
+
+    #[test]
+    #[cfg(not(lock_check))]
+    #[should_panic(expected = "must not acquire the same lock twice")]
+    fn same_lock_twice_panics_without_checks() {
+        let a = Mutex::with_level((), 5);
+        let b = Mutex::with_level((), 5);
+        let _guards = lock!(a, b, a);
+    }
+
+    #[test]
+    fn opposite_orders_do_not_deadlock() {
+        let a = Arc::new(Mutex::with_level(0, 5));
+        let b = Arc::new(Mutex::with_level(0, 5));
+
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let (mut a, mut b) = lock!(*a, *b).unwrap();
+                        *a += 1;
+                        *b += 1;
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let (mut b, mut a) = lock!(*b, *a).unwrap();
+                        *a += 1;
+                        *b += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(4000, *a.lock().unwrap());
+    }
+
+    #[test]
+    fn poisoned_lock_is_acquired() {
+        let a = Mutex::with_level((), 5);
+        let b = Mutex::with_level((), 5);
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = b.lock();
+            panic!("lock is poisoned now");
+        });
+
+        let error = lock!(a, b).unwrap_err();
+        let (_a, _b) = error.into_inner();
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    any::Any,
+    cmp::Ordering,
+    fmt::{Debug, Display, Formatter},
+    panic::{RefUnwindSafe, UnwindSafe},
+    sync::Arc,
+};
+
+/// A level in the lock hierarchy. Higher levels must be acquired first if locks are to be held
+/// simultaneously.
+///
+/// Implemented for the primitive integers, which are all compared by their value, and for tuples
+/// of levels, which are compared lexicographically, e.g. `(subsystem, layer)`. Implement it for
+/// your own types, e.g. an enum of the layers of your application, to see their names in
+/// violations. Levels of different types are not ordered relative to each other, so acquiring a
+/// lock while holding a lock with a level of a different type is a violation. Only
+/// [`lock_all`](crate::lock_all) may acquire them at once. Keep in mind that
+/// [`Mutex::new`](crate::Mutex::new) and friends create locks with the integer level 0.
+///
+/// ```
+/// use lock_hierarchy::{LockLevel, Mutex};
+///
+/// // Declared from lowest to highest, so the derived order fits
+/// #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// enum Layer {
+///     Metrics,
+///     Cache,
+///     Database,
+/// }
+///
+/// impl LockLevel for Layer {}
+///
+/// let database = Mutex::with_level((), Layer::Database);
+/// let cache = Mutex::with_level((), Layer::Cache);
+///
+/// let _database = database.lock().unwrap();
+/// // Fine, Cache is lower than Database
+/// let _cache = cache.lock().unwrap();
+/// ```
+///
+/// Constructors which are `const fn`, e.g. [`OnceLock::with_level`](crate::OnceLock::with_level),
+/// take `u32` levels only.
+///
+/// Names are written by [`Self::fmt_name`], which defaults to [`Debug`] rather than requiring
+/// [`Display`]. Tuples can not implement [`Display`], and the derived [`Debug`] of an enum already
+/// yields the names of its variants.
+pub trait LockLevel: Ord + Debug + Send + Sync + 'static {
+    /// Writes the name of the level, as shown in violations. Defaults to [`Debug`], which yields
+    /// the name of the variant for enums. Forward to [`Display`] to show something else.
+    fn fmt_name(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+macro_rules! impl_integer {
+    ($($integer:ty),*) => {
+        $(impl LockLevel for $integer {})*
+
+        /// Value of `level`, if it is a primitive integer.
+        fn integer(level: &dyn Any) -> Option<i128> {
+            $(
+                if let Some(&level) = level.downcast_ref::<$integer>() {
+                    return Some(level as i128);
+                }
+            )*
+            None
+        }
+    };
+}
+
+impl_integer!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+macro_rules! impl_tuple {
+    ($first:ident $(, $rest:ident)*) => {
+        impl<$first: LockLevel, $($rest: LockLevel),*> LockLevel for ($first, $($rest,)*) {
+            #[allow(non_snake_case)]
+            fn fmt_name(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                let ($first, $($rest,)*) = self;
+                write!(f, "({}", Name($first))?;
+                $(write!(f, ", {}", Name($rest))?;)*
+                f.write_str(")")
+            }
+        }
+    };
+}
+
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+
+/// Displays the name of a level.
+struct Name<'a, L: ?Sized>(&'a L);
+
+impl<L: LockLevel> Display for Name<'_, L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_name(f)
+    }
+}
+
+/// The level of a lock, with its concrete type erased. See [`LockLevel`].
+///
+/// Displays the name of the level. Levels of the same type are ordered like their values, levels
+/// of different types are not ordered at all. See [`LockLevel`].
+#[derive(Clone)]
+pub struct AnyLevel(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    /// All primitive integers, so they are compared by value regardless of their type. Also
+    /// allows for `const` construction from `u32`.
+    Integer(i128),
+    Custom(Arc<dyn Erased>),
+    /// Lower than any other level, i.e. the threshold of blocking sections in which no lock may
+    /// be held at all.
+    Bottom,
+}
+
+/// Object safe part of [`LockLevel`].
+trait Erased: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn compare(&self, other: &dyn Erased) -> Option<Ordering>;
+    fn name(&self, f: &mut Formatter<'_>) -> std::fmt::Result;
+    fn debug(&self, f: &mut Formatter<'_>) -> std::fmt::Result;
+}
+
+impl<L: LockLevel> Erased for L {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn compare(&self, other: &dyn Erased) -> Option<Ordering> {
+        other
+            .as_any()
+            .downcast_ref::<L>()
+            .map(|other| self.cmp(other))
+    }
+
+    fn name(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_name(f)
+    }
+
+    fn debug(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+impl AnyLevel {
+    /// Erases the type of `level`.
+    pub fn new<L: LockLevel>(level: L) -> Self {
+        match integer(&level) {
+            Some(level) => AnyLevel(Repr::Integer(level)),
+            None => AnyLevel(Repr::Custom(Arc::new(level))),
+        }
+    }
+
+    /// Like [`Self::new`], but usable in `const` contexts.
+    pub const fn from_u32(level: u32) -> Self {
+        AnyLevel(Repr::Integer(level as i128))
+    }
+
+    /// Lower than any other level, see [`Repr::Bottom`].
+    #[cfg_attr(not(lock_check), allow(dead_code))]
+    pub(crate) const BOTTOM: AnyLevel = AnyLevel(Repr::Bottom);
+
+    /// Value of the level, if it is a primitive integer.
+    pub fn as_integer(&self) -> Option<i128> {
+        match &self.0 {
+            Repr::Integer(level) => Some(*level),
+            _ => None,
+        }
+    }
+
+    /// The level, if it is of type `L`. Always `None` for primitive integers, see
+    /// [`Self::as_integer`].
+    pub fn downcast_ref<L: LockLevel>(&self) -> Option<&L> {
+        match &self.0 {
+            Repr::Custom(level) => level.as_any().downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Orders all levels, including levels of different types, which [`PartialOrd`] leaves
+    /// unordered.
+    #[cfg(lock_check)]
+    pub(crate) fn total_cmp(&self, other: &AnyLevel) -> Ordering {
+        match (&self.0, &other.0) {
+            (Repr::Bottom, Repr::Bottom) => Ordering::Equal,
+            (Repr::Bottom, _) => Ordering::Less,
+            (_, Repr::Bottom) => Ordering::Greater,
+            (Repr::Integer(level), Repr::Integer(other)) => level.cmp(other),
+            (Repr::Integer(_), Repr::Custom(_)) => Ordering::Less,
+            (Repr::Custom(_), Repr::Integer(_)) => Ordering::Greater,
+            (Repr::Custom(level), Repr::Custom(other)) => level
+                .compare(&**other)
+                .unwrap_or_else(|| level.as_any().type_id().cmp(&other.as_any().type_id())),
+        }
+    }
+
+    /// Whether this level is lower than `other`. `false` for levels of different types.
+    pub(crate) fn is_lower_than(&self, other: &AnyLevel) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Less)
+    }
+}
+
+// Levels are only ever compared and formatted, which must not observe broken invariants even if
+// they contain interior mutability.
+impl UnwindSafe for AnyLevel {}
+impl RefUnwindSafe for AnyLevel {}
+
+impl PartialEq for AnyLevel {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for AnyLevel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (&self.0, &other.0) {
+            (Repr::Bottom, Repr::Bottom) => Some(Ordering::Equal),
+            (Repr::Bottom, _) => Some(Ordering::Less),
+            (_, Repr::Bottom) => Some(Ordering::Greater),
+            (Repr::Integer(level), Repr::Integer(other)) => Some(level.cmp(other)),
+            (Repr::Custom(level), Repr::Custom(other)) => level.compare(&**other),
+            _ => None,
+        }
+    }
+}
+
+impl Display for AnyLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Repr::Integer(level) => Display::fmt(level, f),
+            Repr::Custom(level) => level.name(f),
+            Repr::Bottom => f.write_str("bottom"),
+        }
+    }
+}
+
+impl Debug for AnyLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Repr::Integer(level) => Debug::fmt(level, f),
+            Repr::Custom(level) => level.debug(f),
+            Repr::Bottom => f.write_str("Bottom"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mutex;
+    #[cfg(lock_check)]
+    use crate::{CheckedLockError, LockInfo};
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+    enum Layer {
+        Metrics,
+        Database,
+    }
+
+    impl LockLevel for Layer {}
+
+    #[test]
+    fn integers_are_compared_by_value() {
+        assert!(AnyLevel::new(1u64) == AnyLevel::from_u32(1));
+        assert!(AnyLevel::new(-1i8) < AnyLevel::new(0usize));
+        assert_eq!(Some(3), AnyLevel::new(3u16).as_integer());
+    }
+
+    #[test]
+    fn levels_of_different_types_are_not_ordered() {
+        let layer = AnyLevel::new(Layer::Metrics);
+        let integer = AnyLevel::from_u32(0);
+        assert_eq!(None, layer.partial_cmp(&integer));
+        assert_eq!(None, integer.partial_cmp(&layer));
+        assert_eq!(Some(&Layer::Metrics), layer.downcast_ref::<Layer>());
+        assert_eq!(None, layer.downcast_ref::<(Layer, u32)>());
+    }
+
+    #[test]
+    fn tuples_are_ordered_lexicographically() {
+        let low = AnyLevel::new((Layer::Metrics, 5u32));
+        let high = AnyLevel::new((Layer::Database, 0u32));
+        assert!(low < high);
+        assert_eq!("(Metrics, 5)", low.to_string());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn violation_shows_names() {
+        let database = Mutex::with_level((), Layer::Database);
+        let metrics = Mutex::with_level((), Layer::Metrics);
+
+        let _metrics = metrics.lock().unwrap();
+        let Err(CheckedLockError::Violation(violation)) = database.checked_lock() else {
+            panic!("Expected a violation");
+        };
+        assert_eq!(
+            Some(&Layer::Database),
+            violation
+                .attempted()
+                .map(LockInfo::level)
+                .unwrap()
+                .downcast_ref()
+        );
+        assert!(violation.to_string().starts_with(
+            "Tried to acquire lock with level Database while a lock with level Metrics"
+        ));
+    }
+
+    #[test]
+    fn locks_with_custom_levels_in_order() {
+        let database = Mutex::with_level((), Layer::Database);
+        let metrics = Mutex::with_level((), Layer::Metrics);
+
+        let _database = database.lock().unwrap();
+        // Fine, Metrics is lower than Database
+        let _metrics = metrics.lock().unwrap();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn levels_of_different_types_conflict() {
+        let layer = Mutex::with_level((), Layer::Metrics);
+        let integer = Mutex::new(());
+        let tuple = Mutex::with_level((), (Layer::Database, 1u32));
+
+        let guard = layer.lock().unwrap();
+        // Must fail, integers and layers can not be compared
+        assert!(matches!(
+            integer.checked_lock(),
+            Err(CheckedLockError::Violation(_))
+        ));
+        assert!(matches!(
+            tuple.checked_lock(),
+            Err(CheckedLockError::Violation(_))
+        ));
+        drop(guard);
+
+        let _integer = integer.lock().unwrap();
+        // Must fail in the opposite order, too
+        assert!(matches!(
+            layer.checked_lock(),
+            Err(CheckedLockError::Violation(_))
+        ));
+    }
+}
@@ -1,12 +1,15 @@
 use std::{
     fmt::{Debug, Display, Formatter},
+    marker::PhantomData,
     ops::{Deref, DerefMut},
-    sync::LockResult,
+    panic::Location,
+    ptr::NonNull,
+    sync::{LockResult, PoisonError, TryLockResult},
 };
 
 use crate::{
     level::{Level, LevelGuard},
-    map_guard,
+    map_guard, map_try_guard, CheckedLockResult, LockLevel,
 };
 
 /// Wrapper around a [`std::sync::Mutex`] which uses a thread local variable in order to check for
@@ -24,10 +27,10 @@ use crate::{
 /// // Would panic, lock hierarchy violation
 /// // let _guard_b = mutex_b.lock().unwrap();
 /// ```
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Mutex<T> {
     inner: std::sync::Mutex<T>,
-    level: Level,
+    pub(crate) level: Level,
 }
 
 impl<T> Mutex<T> {
@@ -39,23 +42,108 @@ impl<T> Mutex<T> {
 
     /// Creates a lock and assigns it a level in the lock hierarchy. Higher levels must be acquired
     /// first if locks are to be held simultaneously. This way we can ensure locks are always
-    /// acquired in the same order. This prevents deadlocks.
-    pub fn with_level(t: T, level: u32) -> Self {
+    /// acquired in the same order. This prevents deadlocks. The level may be of any type
+    /// implementing [`LockLevel`].
+    pub fn with_level(t: T, level: impl LockLevel) -> Self {
         Mutex {
             inner: std::sync::Mutex::new(t),
-            level: Level::new(level),
+            level: Level::of(level).protecting::<T>(),
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the lock. The name is part of every violation
+    /// report involving this lock, next to the name of the protected type.
+    pub fn with_name_and_level(t: T, name: &'static str, level: impl LockLevel) -> Self {
+        Mutex {
+            inner: std::sync::Mutex::new(t),
+            level: Level::of(level).named(name).protecting::<T>(),
         }
     }
 
     /// See [std::sync::Mutex::lock]
-    pub fn lock(&self) -> LockResult<MutexGuard<T>> {
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
         let level = self.level.lock();
         map_guard(self.inner.lock(), |guard| MutexGuard {
             inner: guard,
+            mutex: self,
+            _level: level,
+        })
+    }
+
+    /// Like [`Self::lock`], as one of several locks acquired at once by [`lock_all`](crate::lock_all).
+    #[cfg(lock_check)]
+    pub(crate) fn lock_in_group(
+        &self,
+        location: &'static Location<'static>,
+        group: usize,
+    ) -> LockResult<MutexGuard<'_, T>> {
+        let level = self.level.lock_in_group(location, group);
+        map_guard(self.inner.lock(), |guard| MutexGuard {
+            inner: guard,
+            mutex: self,
+            _level: level,
+        })
+    }
+
+    /// Like [`Self::lock`], but returns [`CheckedLockError::Violation`](crate::CheckedLockError)
+    /// instead of reporting a lock hierarchy violation to the violation handler. In that case the
+    /// underlying mutex is not touched, so the caller may back off, e.g. release its locks and
+    /// retry in the right order.
+    #[track_caller]
+    pub fn checked_lock(&self) -> CheckedLockResult<MutexGuard<'_, T>> {
+        let level = self.level.checked_lock()?;
+        Ok(map_guard(self.inner.lock(), |guard| MutexGuard {
+            inner: guard,
+            mutex: self,
+            _level: level,
+        })?)
+    }
+
+    /// See [std::sync::Mutex::try_lock]
+    ///
+    /// A non blocking attempt can not deadlock, so it may happen out of hierarchy order. Once
+    /// acquired, the lock is tracked like any other and later acquisitions are checked against it.
+    #[track_caller]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.level.lock_unchecked();
+        map_try_guard(self.inner.try_lock(), |guard| MutexGuard {
+            inner: guard,
+            mutex: self,
             _level: level,
         })
     }
 
+    /// Like [`Self::try_lock`], as one of several siblings, see [`Self::lock_in_group`].
+    #[cfg(lock_check)]
+    pub(crate) fn try_lock_in_group(
+        &self,
+        location: &'static Location<'static>,
+        group: usize,
+    ) -> TryLockResult<MutexGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.level.lock_unchecked_in_group(location, group);
+        map_try_guard(self.inner.try_lock(), |guard| MutexGuard {
+            inner: guard,
+            mutex: self,
+            _level: level,
+        })
+    }
+
+    /// Whether the current thread holds this mutex. Always `false` if lock hierarchies are not
+    /// checked, e.g. in release builds, so only use it for assertions in debug builds.
+    pub fn is_held_by_current_thread(&self) -> bool {
+        self.level.is_held(false)
+    }
+
+    /// Panics in debug builds if the current thread does not hold this mutex. Use it to ensure the
+    /// caller of a function has locked the mutex.
+    #[track_caller]
+    pub fn assert_held(&self) {
+        self.level.assert_held(false)
+    }
+
     /// See [std::sync::Mutex::get_mut]
     pub fn get_mut(&mut self) -> LockResult<&mut T> {
         // No need to check hierarchy, this does not lock
@@ -69,6 +157,12 @@ impl<T> Mutex<T> {
     }
 }
 
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
 impl<T> From<T> for Mutex<T> {
     /// Creates a new mutex in an unlocked state ready for use.
     /// This is equivalent to [`Mutex::new`].
@@ -78,8 +172,9 @@ impl<T> From<T> for Mutex<T> {
 }
 
 pub struct MutexGuard<'a, T> {
-    inner: std::sync::MutexGuard<'a, T>,
-    _level: LevelGuard,
+    pub(crate) inner: std::sync::MutexGuard<'a, T>,
+    pub(crate) mutex: &'a Mutex<T>,
+    pub(crate) _level: LevelGuard<'a>,
 }
 
 impl<T: Debug> Debug for MutexGuard<'_, T> {
@@ -108,11 +203,235 @@ impl<T> DerefMut for MutexGuard<'_, T> {
     }
 }
 
+impl<'a, T> MutexGuard<'a, T> {
+    /// Unlocks the mutex while `f` runs, e.g. for a callback or blocking work which must not
+    /// happen while holding it. Its level is removed from the stack of held locks for that time,
+    /// too. Afterwards the mutex is locked again, after checking its level against the locks held
+    /// at that time.
+    ///
+    /// Other threads may change the value in the meantime. If one of them poisons the mutex, it is
+    /// locked again anyway and the poisoning is not reported. If locking the mutex again panics,
+    /// the process is aborted, since the guard can neither be restored nor dropped.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    ///
+    /// ```
+    /// use lock_hierarchy::{Mutex, MutexGuard};
+    ///
+    /// let queue = Mutex::with_level(vec![1, 2], 1);
+    /// let io = Mutex::with_level((), 2);
+    ///
+    /// let mut guard = queue.lock().unwrap();
+    /// let job = guard.pop();
+    /// // Fine, the queue is not held while locking the higher level
+    /// MutexGuard::unlocked(&mut guard, || {
+    ///     let _io = io.lock().unwrap();
+    ///     println!("{job:?}");
+    /// });
+    /// assert_eq!(vec![1], *guard);
+    /// ```
+    #[track_caller]
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        let location = Location::caller();
+        let MutexGuard {
+            inner,
+            mutex,
+            _level,
+        } = guard;
+        crate::unlocked(
+            inner,
+            || mutex.inner.lock().unwrap_or_else(PoisonError::into_inner),
+            || _level.released_at(location, f),
+        )
+    }
+
+    /// Projects the guard to a part of the protected value, e.g. a field. The mutex stays locked,
+    /// and its level acquired, until the mapped guard is dropped.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    ///
+    /// ```
+    /// use lock_hierarchy::{Mutex, MutexGuard};
+    ///
+    /// let mutex = Mutex::new((1, String::from("one")));
+    /// let mut name = MutexGuard::map(mutex.lock().unwrap(), |(_, name)| name);
+    /// name.push('!');
+    /// ```
+    pub fn map<U: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedMutexGuard<'a, T, U> {
+        let data = NonNull::from(f(&mut guard));
+        MappedMutexGuard {
+            data,
+            guard,
+            _data: PhantomData,
+        }
+    }
+
+    /// Like [`Self::map`], but `f` may fail, in which case the original guard is returned.
+    pub fn try_map<U: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedMutexGuard<'a, T, U>, Self> {
+        match f(&mut guard).map(NonNull::from) {
+            Some(data) => Ok(MappedMutexGuard {
+                data,
+                guard,
+                _data: PhantomData,
+            }),
+            None => Err(guard),
+        }
+    }
+}
+
+/// A [`MutexGuard`] projected to a part `U` of the protected value `T` by [`MutexGuard::map`].
+pub struct MappedMutexGuard<'a, T, U: ?Sized> {
+    /// Points into the value protected by `guard`.
+    data: NonNull<U>,
+    /// Keeps the mutex locked and its level acquired.
+    guard: MutexGuard<'a, T>,
+    /// Invariant in `U`, like a mutable reference.
+    _data: PhantomData<&'a mut U>,
+}
+
+// SAFETY: Sharing the guard only allows for shared access to `U`, and to nothing else.
+unsafe impl<T: Sync, U: ?Sized + Sync> Sync for MappedMutexGuard<'_, T, U> {}
+
+impl<'a, T, U: ?Sized> MappedMutexGuard<'a, T, U> {
+    /// Projects the guard further, see [`MutexGuard::map`].
+    pub fn map<V: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut U) -> &mut V,
+    ) -> MappedMutexGuard<'a, T, V> {
+        let data = NonNull::from(f(&mut guard));
+        MappedMutexGuard {
+            data,
+            guard: guard.guard,
+            _data: PhantomData,
+        }
+    }
+
+    /// Like [`Self::map`], but `f` may fail, in which case the original guard is returned.
+    pub fn try_map<V: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut U) -> Option<&mut V>,
+    ) -> Result<MappedMutexGuard<'a, T, V>, Self> {
+        match f(&mut guard).map(NonNull::from) {
+            Some(data) => Ok(MappedMutexGuard {
+                data,
+                guard: guard.guard,
+                _data: PhantomData,
+            }),
+            None => Err(guard),
+        }
+    }
+}
+
+impl<T, U: ?Sized + Debug> Debug for MappedMutexGuard<'_, T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T, U: ?Sized + Display> Display for MappedMutexGuard<'_, T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T, U: ?Sized> Deref for MappedMutexGuard<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        // SAFETY: `data` has been borrowed mutably from `guard`, which is not used otherwise
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<T, U: ?Sized> DerefMut for MappedMutexGuard<'_, T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        // SAFETY: `data` has been borrowed mutably from `guard`, which is not used otherwise
+        unsafe { self.data.as_mut() }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{hint::black_box, sync::Arc, thread};
+    use std::{
+        hint::black_box,
+        sync::{Arc, TryLockError},
+        thread,
+    };
 
     use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::CheckedLockError;
+
+    #[test]
+    fn map_guard_to_field() {
+        let mutex = Mutex::with_level((1, vec![2, 3]), 1);
+        let lower = Mutex::with_level((), 0);
+
+        let guard = MutexGuard::map(mutex.lock().unwrap(), |(_, values)| values);
+        let mut guard = MappedMutexGuard::try_map(guard, |values| values.get_mut(1)).unwrap();
+        *guard += 1;
+        // Fine, 0 is lower than 1, which is still held
+        let _lower = lower.lock().unwrap();
+        #[cfg(lock_check)]
+        assert_eq!(2, crate::held_lock_count());
+        let guard = MappedMutexGuard::try_map(guard, |_| None::<&mut ()>).unwrap_err();
+        assert_eq!(4, *guard);
+    }
+
+    #[test]
+    fn unlocked_releases_mutex_and_level() {
+        let mutex = Mutex::with_level(1, 1);
+
+        let mut guard = mutex.lock().unwrap();
+        MutexGuard::unlocked(&mut guard, || {
+            #[cfg(lock_check)]
+            assert!(!mutex.is_held_by_current_thread());
+            thread::scope(|s| {
+                s.spawn(|| *mutex.lock().unwrap() += 1);
+            });
+        });
+        #[cfg(lock_check)]
+        mutex.assert_held();
+        assert_eq!(2, *guard);
+    }
+
+    #[test]
+    fn unlocked_locks_again_if_closure_panics() {
+        let mutex = Mutex::new(1);
+
+        let mut guard = mutex.lock().unwrap();
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            MutexGuard::unlocked(&mut guard, || panic!("the mutex is locked again anyway"))
+        }))
+        .unwrap_err();
+        *guard += 1;
+        assert!(matches!(mutex.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(guard);
+        assert_eq!(2, *mutex.lock().unwrap());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn unlocked_checks_level_again() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::with_level((), 1);
+                let lower = Mutex::with_level((), 0);
+
+                let mut guard = mutex.lock().unwrap();
+                // Must panic, level 0 is held once level 1 is acquired again
+                let _lower = MutexGuard::unlocked(&mut guard, || lower.lock().unwrap());
+            }),
+            (Some(1), vec![0])
+        );
+    }
 
     #[test]
     fn acquire_resource() {
@@ -146,23 +465,146 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn self_deadlock() {
-        // This ensures that the level is locked in Mutex::lock before locking the std lock which might otherwise cause an unchecked deadlock
+        assert_eq!(
+            catch_violation(|| {
+                // This ensures that the level is locked in Mutex::lock before locking the std lock which might otherwise cause an unchecked deadlock
+                let mutex = Mutex::new(());
+                let _guard = mutex.lock().unwrap();
+                let _guard = mutex.lock().unwrap();
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn poisoned_lock() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = Mutex::new(());
+                std::panic::catch_unwind(|| {
+                    let _guard = mutex.lock();
+                    panic!("lock is poisoned now");
+                })
+                .unwrap_err();
+
+                let _guard_a = mutex.lock().unwrap_err().into_inner();
+                let _guard_b = mutex.lock();
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn correct_level_locked() {
+        let mutex = Mutex::with_level((), 1);
+        let _guard_a = mutex.lock().unwrap();
+        assert_eq!(_guard_a._level.level.level.as_integer(), Some(1));
+
         let mutex = Mutex::new(());
-        let _guard = mutex.lock().unwrap();
-        let _guard = mutex.lock().unwrap();
+        let _guard_a = mutex.lock().unwrap();
+        assert_eq!(_guard_a._level.level.level.as_integer(), Some(0));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn checked_lock_violation() {
+        let mutex_a = Mutex::new(());
+        let mutex_b = Mutex::new(());
+        let _guard_a = mutex_a.lock().unwrap();
+        let err = mutex_b.checked_lock().unwrap_err();
+        assert!(matches!(err, CheckedLockError::Violation(_)));
+        // Fine, the failed attempt did not lock mutex b
+        assert!(mutex_b.try_lock().is_ok());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn violation_reports_locations() {
+        let mutex_a = Mutex::new(());
+        let mutex_b = Mutex::new(());
+        let line = line!() + 1;
+        let _guard_a = mutex_a.lock().unwrap();
+        let Err(CheckedLockError::Violation(violation)) = mutex_b.checked_lock() else {
+            panic!("Expected a violation");
+        };
+        let held = violation.held()[0].location();
+        assert_eq!((file!(), line), (held.file(), held.line()));
+        let attempted = violation.attempted().unwrap().location();
+        assert_eq!((file!(), line + 1), (attempted.file(), attempted.line()));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn violation_reports_names() {
+        let accounts = Mutex::with_name_and_level(Vec::<u32>::new(), "accounts", 1);
+        let audit = Mutex::with_name_and_level(String::new(), "audit", 1);
+        let _guard = accounts.lock().unwrap();
+        let Err(CheckedLockError::Violation(violation)) = audit.checked_lock() else {
+            panic!("Expected a violation");
+        };
+        let held = &violation.held()[0];
+        assert_eq!(Some("accounts"), held.name());
+        assert_eq!(Some("alloc::vec::Vec<u32>"), held.type_name());
+        assert_eq!(Some("audit"), violation.attempted().unwrap().name());
+        assert!(violation.to_string().starts_with(
+            "Tried to acquire lock \"audit\" with level 1 while lock \"accounts\" with level 1 is \
+            acquired."
+        ));
+        assert!(violation
+            .to_string()
+            .contains("held: level 1 \"accounts\" (alloc::vec::Vec<u32>) at src/mutex.rs:"));
+    }
+
+    #[test]
+    #[cfg(all(lock_check, feature = "backtrace"))]
+    fn violation_reports_backtraces() {
+        use std::backtrace::BacktraceStatus;
+
+        let mutex_a = Mutex::new(());
+        let mutex_b = Mutex::new(());
+        let _guard_a = mutex_a.lock().unwrap();
+        let Err(CheckedLockError::Violation(violation)) = mutex_b.checked_lock() else {
+            panic!("Expected a violation");
+        };
+        let held = violation.held()[0].backtrace().unwrap();
+        assert_eq!(BacktraceStatus::Captured, held.status());
+        let attempted = violation.attempted().unwrap().backtrace().unwrap();
+        assert_eq!(BacktraceStatus::Captured, attempted.status());
+        assert_eq!(2, violation.to_string().matches("\n  backtrace:\n").count());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn is_held_by_current_thread() {
+        let mutex_a = Mutex::with_level((), 1);
+        let mutex_b = Mutex::with_level((), 1);
+        assert!(!mutex_a.is_held_by_current_thread());
+
+        let guard_a = mutex_a.lock().unwrap();
+        // Same level, but a different instance
+        let _guard_b = mutex_b.try_lock().unwrap();
+        mutex_a.assert_held();
+        drop(guard_a);
+        assert!(!mutex_a.is_held_by_current_thread());
+        assert!(mutex_b.is_held_by_current_thread());
     }
 
     #[test]
     #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
+        expected = "Lock \"config\" with level 0 is not held by the current thread for exclusive access."
     )]
-    #[cfg(debug_assertions)]
-    fn poisoned_lock() {
+    #[cfg(lock_check)]
+    fn assert_held_panics_if_not_held() {
+        let mutex = Mutex::with_name_and_level((), "config", 0);
+        mutex.assert_held();
+    }
+
+    #[test]
+    fn checked_lock_poisoned() {
         let mutex = Mutex::new(());
         std::panic::catch_unwind(|| {
             let _guard = mutex.lock();
@@ -170,33 +612,51 @@ mod tests {
         })
         .unwrap_err();
 
-        let _guard_a = mutex.lock().unwrap_err().into_inner();
-        let _guard_b = mutex.lock();
+        let err = mutex.checked_lock().unwrap_err();
+        assert!(matches!(err, CheckedLockError::Poisoned(_)));
     }
 
     #[test]
-    #[cfg(debug_assertions)]
-    fn correct_level_locked() {
-        let mutex = Mutex::with_level((), 1);
-        let _guard_a = mutex.lock().unwrap();
-        assert_eq!(_guard_a._level.level, 1);
+    fn try_lock_out_of_order() {
+        let mutex_a = Mutex::new(());
+        let mutex_b = Mutex::with_level((), 1);
+        let _guard_a = mutex_a.lock().unwrap();
+        // Fine, try_lock does not block and can therefore not deadlock
+        let _guard_b = mutex_b.try_lock().unwrap();
+    }
 
+    #[test]
+    fn try_lock_would_block() {
         let mutex = Mutex::new(());
-        let _guard_a = mutex.lock().unwrap();
-        assert_eq!(_guard_a._level.level, 0);
+        let _guard = mutex.lock().unwrap();
+        assert!(matches!(mutex.try_lock(), Err(TryLockError::WouldBlock)));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn lock_after_try_lock() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = Mutex::new(());
+                let mutex_b = Mutex::new(());
+                let _guard_a = mutex_a.try_lock().unwrap();
+                let _guard_b = mutex_b.lock().unwrap();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn created_by_default_impl_should_be_level_0() {
         let mutex = Mutex::<()>::default();
-        assert_eq!(mutex.level.level, 0);
+        assert_eq!(mutex.level.level.as_integer(), Some(0));
     }
 
     #[test]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn mutex_created_by_from_impl_should_be_level_0() {
         let mutex: Mutex<u8> = 42.into();
-        assert_eq!(mutex.level.level, 0);
+        assert_eq!(mutex.level.level.as_integer(), Some(0));
     }
 }
@@ -0,0 +1,240 @@
+use std::sync::OnceState;
+#[cfg(lock_check)]
+use std::{
+    sync::PoisonError,
+    thread::{self, ThreadId},
+};
+
+use crate::level::Level;
+
+/// Wrapper around a [`std::sync::Once`] which treats running the initialization as acquiring a
+/// lock with its level.
+///
+/// See the [crate level documentation](crate) for more general information.
+///
+/// ```
+/// use lock_hierarchy::{Mutex, Once};
+///
+/// static INIT: Once = Once::with_level(1);
+/// let mutex = Mutex::new(()); // Level 0
+///
+/// // Fine, 0 is lower level than 1
+/// INIT.call_once(|| drop(mutex.lock().unwrap()));
+/// let _guard = mutex.lock().unwrap();
+/// // Fine, already completed. This does not block.
+/// INIT.call_once(|| unreachable!());
+/// ```
+#[derive(Debug)]
+pub struct Once {
+    inner: std::sync::Once,
+    level: Level,
+    initialization: Initialization,
+}
+
+impl Once {
+    /// Creates a `Once` with level 0. Running its initialization while holding any other lock
+    /// causes an error in debug builds.
+    pub const fn new() -> Self {
+        Self::with_level(0)
+    }
+
+    /// Creates a `Once` and assigns it a level in the lock hierarchy. Locks acquired during the
+    /// initialization must have a lower level.
+    ///
+    /// Takes a `u32` only, so it can be used in statics. Custom [`LockLevel`](crate::LockLevel)s,
+    /// e.g. enums, are not supported.
+    pub const fn with_level(level: u32) -> Self {
+        Once {
+            inner: std::sync::Once::new(),
+            level: Level::new(level),
+            initialization: Initialization::new(),
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the `Once`. The name is part of every violation
+    /// report involving its initialization.
+    ///
+    /// Takes a `u32` level only, like [`Self::with_level`].
+    pub const fn with_name_and_level(name: &'static str, level: u32) -> Self {
+        Once {
+            inner: std::sync::Once::new(),
+            level: Level::new(level).named(name),
+            initialization: Initialization::new(),
+        }
+    }
+
+    /// See [std::sync::Once::call_once]
+    #[track_caller]
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.inner.is_completed() {
+            return;
+        }
+        self.initialization.check_recursion(&self.level);
+        let _level = self.level.lock();
+        self.inner.call_once(|| self.initialization.run(f))
+    }
+
+    /// See [std::sync::Once::call_once_force]
+    #[track_caller]
+    pub fn call_once_force<F: FnOnce(&OnceState)>(&self, f: F) {
+        if self.inner.is_completed() {
+            return;
+        }
+        self.initialization.check_recursion(&self.level);
+        let _level = self.level.lock();
+        self.inner
+            .call_once_force(|state| self.initialization.run(|| f(state)))
+    }
+
+    /// See [std::sync::Once::is_completed]
+    pub fn is_completed(&self) -> bool {
+        self.inner.is_completed()
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Remembers which thread runs an initialization, so a recursive initialization can be reported,
+/// rather than deadlocking or being reported as a regular lock hierarchy violation.
+#[derive(Debug, Default)]
+pub(crate) struct Initialization {
+    #[cfg(lock_check)]
+    thread: std::sync::Mutex<Option<ThreadId>>,
+}
+
+impl Initialization {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(lock_check)]
+            thread: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Panics if the current thread is already running this initialization.
+    #[inline]
+    pub fn check_recursion(&self, level: &Level) {
+        #[cfg(lock_check)]
+        if *self.thread.lock().unwrap_or_else(PoisonError::into_inner)
+            == Some(thread::current().id())
+        {
+            match level.name {
+                Some(name) => panic!(
+                    "Recursive initialization of lazily initialized value {name:?} with level {}. \
+                    This would deadlock.",
+                    level.level
+                ),
+                None => panic!(
+                    "Recursive initialization of a lazily initialized value with level {}. This \
+                    would deadlock.",
+                    level.level
+                ),
+            }
+        }
+        #[cfg(not(lock_check))]
+        let _ = level;
+    }
+
+    /// Marks the current thread as running the initialization while `f` runs.
+    #[inline]
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        #[cfg(lock_check)]
+        {
+            /// Resets the initializing thread, even if `f` panics.
+            struct Reset<'a>(&'a Initialization);
+
+            impl Drop for Reset<'_> {
+                fn drop(&mut self) {
+                    *self.0.thread.lock().unwrap_or_else(PoisonError::into_inner) = None;
+                }
+            }
+
+            *self.thread.lock().unwrap_or_else(PoisonError::into_inner) =
+                Some(thread::current().id());
+            let _reset = Reset(self);
+            f()
+        }
+        #[cfg(not(lock_check))]
+        f()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::Mutex;
+
+    #[test]
+    fn call_once() {
+        let once = Once::new();
+        let mut calls = 0;
+        once.call_once(|| calls += 1);
+        once.call_once(|| calls += 1);
+        assert_eq!(1, calls);
+        assert!(once.is_completed());
+    }
+
+    #[test]
+    fn completed_once_is_not_checked() {
+        let once = Once::new();
+        let mutex = Mutex::new(());
+        once.call_once(|| ());
+        let _guard = mutex.lock().unwrap();
+        // Fine, the initialization has already completed
+        once.call_once(|| ());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn initialization_is_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let once = Once::new();
+                let mutex = Mutex::new(());
+                let _guard = mutex.lock().unwrap();
+                // Must panic, initialization counts as acquiring level 0
+                once.call_once(|| ());
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn acquisitions_within_initialization_are_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let once = Once::with_level(1);
+                let mutex = Mutex::with_level((), 1);
+                // Must panic, the mutex must have a lower level than the initialization
+                once.call_once(|| drop(mutex.lock().unwrap()));
+            }),
+            (Some(1), vec![1])
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Recursive initialization of a lazily initialized value with level 0. This would deadlock."
+    )]
+    #[cfg(lock_check)]
+    fn recursive_initialization() {
+        let once = Once::new();
+        once.call_once_force(|_| once.call_once(|| ()));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Recursive initialization of lazily initialized value \"config\" with level 0. This would deadlock."
+    )]
+    #[cfg(lock_check)]
+    fn recursive_initialization_reports_name() {
+        let once = Once::with_name_and_level("config", 0);
+        once.call_once_force(|_| once.call_once(|| ()));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{level::Level, once::Initialization};
+
+/// Wrapper around a [`std::sync::OnceLock`] which treats running the initialization as acquiring a
+/// lock with its level.
+///
+/// See the [crate level documentation](crate) for more general information.
+///
+/// ```
+/// use lock_hierarchy::{Mutex, OnceLock};
+///
+/// let cell = OnceLock::new(); // Level 0
+/// let mutex = Mutex::new(42); // also level 0
+/// let guard = mutex.lock().unwrap();
+/// // Would panic, initialization counts as acquiring a lock with level 0
+/// // cell.get_or_init(|| 42);
+/// drop(guard);
+/// // Fine, no other lock is held
+/// assert_eq!(42, *cell.get_or_init(|| 42));
+/// ```
+#[derive(Debug)]
+pub struct OnceLock<T> {
+    inner: std::sync::OnceLock<T>,
+    level: Level,
+    initialization: Initialization,
+}
+
+impl<T> OnceLock<T> {
+    /// Creates an empty cell with level 0. Initializing it while holding any other lock causes an
+    /// error in debug builds.
+    pub const fn new() -> Self {
+        Self::with_level(0)
+    }
+
+    /// Creates an empty cell and assigns it a level in the lock hierarchy. Locks acquired during
+    /// the initialization must have a lower level.
+    ///
+    /// Takes a `u32` only, so it can be used in statics. Custom [`LockLevel`](crate::LockLevel)s,
+    /// e.g. enums, are not supported.
+    pub const fn with_level(level: u32) -> Self {
+        OnceLock {
+            inner: std::sync::OnceLock::new(),
+            level: Level::new(level).protecting::<T>(),
+            initialization: Initialization::new(),
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the cell. The name is part of every violation
+    /// report involving its initialization.
+    ///
+    /// Takes a `u32` level only, like [`Self::with_level`].
+    pub const fn with_name_and_level(name: &'static str, level: u32) -> Self {
+        OnceLock {
+            inner: std::sync::OnceLock::new(),
+            level: Level::new(level).named(name).protecting::<T>(),
+            initialization: Initialization::new(),
+        }
+    }
+
+    /// See [std::sync::OnceLock::get]
+    pub fn get(&self) -> Option<&T> {
+        // No need to check hierarchy, this does not block
+        self.inner.get()
+    }
+
+    /// See [std::sync::OnceLock::get_mut]
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        // No need to check hierarchy, this does not block
+        self.inner.get_mut()
+    }
+
+    /// See [std::sync::OnceLock::set]
+    #[track_caller]
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.inner.get().is_some() {
+            return Err(value);
+        }
+        // Blocks while another thread is initializing the cell
+        let _level = self.level.lock();
+        self.inner.set(value)
+    }
+
+    /// See [std::sync::OnceLock::get_or_init]
+    #[track_caller]
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self.inner.get() {
+            return value;
+        }
+        self.initialization.check_recursion(&self.level);
+        let _level = self.level.lock();
+        self.inner.get_or_init(|| self.initialization.run(f))
+    }
+
+    /// See [std::sync::OnceLock::into_inner]
+    pub fn into_inner(self) -> Option<T> {
+        // No need to check hierarchy, this does not block
+        self.inner.into_inner()
+    }
+
+    /// See [std::sync::OnceLock::take]
+    pub fn take(&mut self) -> Option<T> {
+        // No need to check hierarchy, this does not block
+        self.inner.take()
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<T> for OnceLock<T> {
+    /// Creates a new cell with level 0, which is already initialized with `value`.
+    fn from(value: T) -> Self {
+        OnceLock {
+            inner: std::sync::OnceLock::from(value),
+            level: Level::new(0).protecting::<T>(),
+            initialization: Initialization::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{hint::black_box, sync::Arc, thread};
+
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::Mutex;
+
+    #[test]
+    fn initialize_once() {
+        let cell = OnceLock::new();
+        assert_eq!(None, cell.get());
+        assert_eq!(42, *cell.get_or_init(|| 42));
+        assert_eq!(42, *cell.get_or_init(|| 43));
+        assert_eq!(Err(44), cell.set(44));
+        assert_eq!(Some(42), cell.into_inner());
+    }
+
+    #[test]
+    fn multithreaded() {
+        let cell = Arc::new(OnceLock::new());
+        let thread = thread::spawn({
+            let cell = cell.clone();
+            move || {
+                black_box(cell.get_or_init(|| 42));
+            }
+        });
+        black_box(cell.get_or_init(|| 42));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn initialized_cell_is_not_checked() {
+        let cell = OnceLock::from(42);
+        let mutex = Mutex::new(());
+        let _guard = mutex.lock().unwrap();
+        // Fine, the cell is already initialized
+        assert_eq!(42, *cell.get_or_init(|| 43));
+    }
+
+    #[test]
+    fn lower_level_within_initialization() {
+        let cell = OnceLock::with_level(1);
+        let mutex = Mutex::new(42);
+        // Fine: 0 is lower level than 1
+        cell.get_or_init(|| *mutex.lock().unwrap());
+        // Fine, initialization has finished
+        let _guard = mutex.lock().unwrap();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn initialization_is_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let cell = OnceLock::new();
+                let mutex = Mutex::new(());
+                let _guard = mutex.lock().unwrap();
+                // Must panic, initialization counts as acquiring level 0
+                cell.get_or_init(|| ());
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn set_is_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let cell = OnceLock::new();
+                let mutex = Mutex::new(());
+                let _guard = mutex.lock().unwrap();
+                // Must panic, set blocks while the cell is initialized by another thread
+                let _ = cell.set(());
+            }),
+            (Some(0), vec![0])
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Recursive initialization of a lazily initialized value with level 3. This would deadlock."
+    )]
+    #[cfg(lock_check)]
+    fn recursive_initialization() {
+        let cell = OnceLock::with_level(3);
+        cell.get_or_init(|| *cell.get_or_init(|| 42));
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    fmt::{Debug, Display, Formatter},
+    marker::PhantomData,
+    ops::Deref,
+    sync::PoisonError,
+    thread::{self, ThreadId},
+};
+
+use crate::{level::Level, LockLevel};
+
+/// A mutex which may be locked again by the thread already holding it, e.g. by callbacks. Like
+/// for [`std::cell::RefCell`] within a [`std::rc::Rc`], access is shared only, so nested guards
+/// can not alias a mutable reference. Wrap the value in a `RefCell` to mutate it.
+///
+/// The first acquisition by a thread is checked against the lock hierarchy like for any other
+/// lock. Nested acquisitions by the same thread are always allowed and do not put the level on
+/// the stack again. The level is released once the last guard of the thread is dropped.
+///
+/// Unlike [`Mutex`](crate::Mutex), a reentrant mutex is not poisoned, since its value can not be
+/// mutated through it.
+///
+/// ```
+/// use std::cell::RefCell;
+///
+/// use lock_hierarchy::ReentrantMutex;
+///
+/// let mutex = ReentrantMutex::with_level(RefCell::new(Vec::new()), 1);
+///
+/// let outer = mutex.lock();
+/// outer.borrow_mut().push("outer");
+/// // Fine, the current thread already holds the mutex
+/// let inner = mutex.lock();
+/// inner.borrow_mut().push("inner");
+/// ```
+pub struct ReentrantMutex<T> {
+    owner: std::sync::Mutex<Owner>,
+    released: std::sync::Condvar,
+    level: Level,
+    data: T,
+}
+
+// SAFETY: Only the thread owning the lock accesses `data`, and only for shared access, like for
+// the reentrant lock guarding `std::io::Stdout`.
+unsafe impl<T: Send> Sync for ReentrantMutex<T> {}
+
+#[derive(Debug, Default)]
+struct Owner {
+    thread: Option<ThreadId>,
+    /// Number of guards held by `thread`.
+    count: usize,
+}
+
+impl<T> ReentrantMutex<T> {
+    /// Creates lock with level 0. Use this constructor if you want to get an error in debug builds
+    /// every time you acquire another lock while holding this one.
+    pub fn new(t: T) -> Self {
+        Self::with_level(t, 0)
+    }
+
+    /// Creates a lock and assigns it a level in the lock hierarchy. Higher levels must be acquired
+    /// first if locks are to be held simultaneously. This way we can ensure locks are always
+    /// acquired in the same order. This prevents deadlocks.
+    pub fn with_level(t: T, level: impl LockLevel) -> Self {
+        ReentrantMutex {
+            owner: std::sync::Mutex::default(),
+            released: std::sync::Condvar::new(),
+            level: Level::of(level).protecting::<T>(),
+            data: t,
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the lock. The name is part of every violation
+    /// report involving this lock, next to the name of the protected type.
+    pub fn with_name_and_level(t: T, name: &'static str, level: impl LockLevel) -> Self {
+        ReentrantMutex {
+            owner: std::sync::Mutex::default(),
+            released: std::sync::Condvar::new(),
+            level: Level::of(level).named(name).protecting::<T>(),
+            data: t,
+        }
+    }
+
+    fn owner(&self) -> std::sync::MutexGuard<'_, Owner> {
+        // The owner is consistent after every statement, so a panic can not leave it corrupted
+        self.owner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquires the mutex, blocking the current thread until it is available. The lock hierarchy
+    /// is only checked if the current thread does not hold the mutex yet.
+    #[track_caller]
+    pub fn lock(&self) -> ReentrantMutexGuard<'_, T> {
+        let current = thread::current().id();
+        let mut owner = self.owner();
+        if owner.thread != Some(current) {
+            // Check before waiting, since waiting may deadlock. Do not hold the owner while
+            // reporting, the violation handler may inspect the mutex.
+            drop(owner);
+            self.level.acquire();
+            owner = self.owner();
+            while owner.thread.is_some() {
+                owner = self
+                    .released
+                    .wait(owner)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            owner.thread = Some(current);
+        }
+        owner.count += 1;
+        ReentrantMutexGuard::new(self)
+    }
+
+    /// Attempts to acquire the mutex without blocking. Succeeds if the mutex is free or already
+    /// held by the current thread.
+    ///
+    /// A non blocking attempt can not deadlock, so it may happen out of hierarchy order. Once
+    /// acquired, the lock is tracked like any other and later acquisitions are checked against it.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<ReentrantMutexGuard<'_, T>> {
+        let current = thread::current().id();
+        let mut owner = self.owner();
+        match owner.thread {
+            Some(thread) if thread != current => return None,
+            Some(_) => (),
+            None => {
+                self.level.acquire_unchecked();
+                owner.thread = Some(current);
+            }
+        }
+        owner.count += 1;
+        Some(ReentrantMutexGuard::new(self))
+    }
+
+    /// Whether the current thread holds this mutex. Unlike for other locks, this is also known if
+    /// lock hierarchies are not checked.
+    pub fn is_held_by_current_thread(&self) -> bool {
+        self.owner().thread == Some(thread::current().id())
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    pub fn get_mut(&mut self) -> &mut T {
+        // No need to check hierarchy, this does not lock
+        &mut self.data
+    }
+
+    /// Consumes this mutex, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        // No need to check hierarchy, this does not lock
+        self.data
+    }
+}
+
+impl<T: Default> Default for ReentrantMutex<T> {
+    fn default() -> Self {
+        ReentrantMutex::new(T::default())
+    }
+}
+
+impl<T> From<T> for ReentrantMutex<T> {
+    /// Creates a new mutex in an unlocked state ready for use.
+    /// This is equivalent to [`ReentrantMutex::new`].
+    fn from(value: T) -> Self {
+        ReentrantMutex::new(value)
+    }
+}
+
+impl<T> Debug for ReentrantMutex<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReentrantMutex")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Shared access to the value of a [`ReentrantMutex`]. The mutex is released once the last guard
+/// of the owning thread is dropped.
+pub struct ReentrantMutexGuard<'a, T> {
+    mutex: &'a ReentrantMutex<T>,
+    /// The mutex is owned by a thread, so the guard must be dropped by that thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> ReentrantMutexGuard<'a, T> {
+    fn new(mutex: &'a ReentrantMutex<T>) -> Self {
+        ReentrantMutexGuard {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+// SAFETY: The guard only hands out shared references, so sharing it is fine if `T` is `Sync`.
+unsafe impl<T: Sync> Sync for ReentrantMutexGuard<'_, T> {}
+
+impl<T> Drop for ReentrantMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut owner = self.mutex.owner();
+        owner.count -= 1;
+        if owner.count == 0 {
+            owner.thread = None;
+            self.mutex.level.release();
+            drop(owner);
+            self.mutex.released.notify_one();
+        }
+    }
+}
+
+impl<T: Debug> Debug for ReentrantMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T: Display> Display for ReentrantMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T> Deref for ReentrantMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.mutex.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        sync::{mpsc, Arc},
+    };
+
+    use super::*;
+    use crate::Mutex;
+    #[cfg(lock_check)]
+    use crate::{held_lock_count, violation::catch_violation};
+
+    #[test]
+    fn nested_acquisitions() {
+        let mutex = ReentrantMutex::with_level(Cell::new(0), 1);
+        let lower = Mutex::with_level((), 0);
+
+        let outer = mutex.lock();
+        let inner = mutex.lock();
+        inner.set(inner.get() + 1);
+        #[cfg(lock_check)]
+        assert_eq!(1, held_lock_count());
+        // Fine, 0 is lower than 1
+        let _lower = lower.lock().unwrap();
+        // Fine, the guards may be dropped in any order
+        drop(outer);
+        assert!(mutex.is_held_by_current_thread());
+        assert_eq!(1, inner.get());
+        drop(inner);
+        assert!(!mutex.is_held_by_current_thread());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn first_acquisition_is_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = ReentrantMutex::with_level((), 1);
+                let lower = Mutex::with_level((), 0);
+
+                let _lower = lower.lock().unwrap();
+                // Must panic, 1 is higher than 0
+                let _guard = mutex.lock();
+            }),
+            (Some(1), vec![0])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn nested_acquisition_is_not_checked() {
+        let mutex = ReentrantMutex::with_level((), 1);
+        let lower = Mutex::with_level((), 0);
+
+        let _outer = mutex.lock();
+        let _lower = lower.lock().unwrap();
+        // Fine, the mutex is already held
+        let _inner = mutex.lock();
+    }
+
+    #[test]
+    fn other_thread_waits() {
+        let mutex = Arc::new(ReentrantMutex::new(Cell::new(0)));
+
+        let guard = mutex.lock();
+        let (tried, has_tried) = mpsc::channel();
+        let other = {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                assert!(mutex.try_lock().is_none());
+                tried.send(()).unwrap();
+                mutex.lock().get()
+            })
+        };
+        has_tried.recv().unwrap();
+        guard.set(42);
+        drop(guard);
+        assert_eq!(42, other.join().unwrap());
+    }
+}
@@ -1,12 +1,15 @@
 use std::{
     fmt::{Debug, Display, Formatter},
+    marker::PhantomData,
     ops::{Deref, DerefMut},
-    sync::LockResult,
+    panic::Location,
+    ptr::NonNull,
+    sync::{LockResult, PoisonError, TryLockError, TryLockResult},
 };
 
 use crate::{
     level::{Level, LevelGuard},
-    map_guard,
+    map_guard, map_try_guard, CheckedLockResult, LockLevel,
 };
 
 /// Wrapper around a [`std::sync::RwLock`] which uses a thread local variable in order to check for
@@ -24,7 +27,9 @@ use crate::{
 /// // Would panic, lock hierarchy violation
 /// // let _guard_b = mutex_b.read().unwrap();
 /// ```
-#[derive(Debug, Default)]
+///
+/// See [`UpgradableRwLock`] for a lock which also offers upgradable reads.
+#[derive(Debug)]
 pub struct RwLock<T> {
     inner: std::sync::RwLock<T>,
     level: Level,
@@ -39,32 +44,138 @@ impl<T> RwLock<T> {
 
     /// Creates a lock and assigns it a level in the lock hierarchy. Higher levels must be acquired
     /// first if locks are to be held simultaneously. This way we can ensure locks are always
-    /// acquired in the same order. This prevents deadlocks.
-    pub fn with_level(t: T, level: u32) -> Self {
+    /// acquired in the same order. This prevents deadlocks. The level may be of any type
+    /// implementing [`LockLevel`].
+    pub fn with_level(t: T, level: impl LockLevel) -> Self {
+        RwLock {
+            inner: std::sync::RwLock::new(t),
+            level: Level::of(level).protecting::<T>(),
+        }
+    }
+
+    /// Like [`Self::with_level`], but also names the lock. The name is part of every violation
+    /// report involving this lock, next to the name of the protected type.
+    pub fn with_name_and_level(t: T, name: &'static str, level: impl LockLevel) -> Self {
         RwLock {
             inner: std::sync::RwLock::new(t),
-            level: Level::new(level),
+            level: Level::of(level).named(name).protecting::<T>(),
         }
     }
 
     /// See [std::sync::RwLock::read]
-    pub fn read(&self) -> LockResult<RwLockReadGuard<T>> {
-        let level = self.level.lock();
+    #[track_caller]
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        let level = self.level.lock().shared();
         map_guard(self.inner.read(), |guard| RwLockReadGuard {
             inner: guard,
+            lock: self,
             _level: level,
         })
     }
 
     /// See [std::sync::RwLock::write]
-    pub fn write(&self) -> LockResult<RwLockWriteGuard<T>> {
+    #[track_caller]
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
         let level = self.level.lock();
         map_guard(self.inner.write(), |guard| RwLockWriteGuard {
             inner: guard,
+            lock: self,
             _level: level,
         })
     }
 
+    /// Acquires `inner` for reading again, on behalf of a guard whose acquisition has reported
+    /// poisoning already.
+    fn relock_read(&self) -> std::sync::RwLockReadGuard<'_, T> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquires `inner` for writing again, on behalf of a guard whose acquisition has reported
+    /// poisoning already.
+    fn relock_write(&self) -> std::sync::RwLockWriteGuard<'_, T> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Like [`Self::read`], but returns [`CheckedLockError::Violation`](crate::CheckedLockError)
+    /// instead of reporting a lock hierarchy violation to the violation handler. In that case the
+    /// underlying lock is not touched, so the caller may back off.
+    #[track_caller]
+    pub fn checked_read(&self) -> CheckedLockResult<RwLockReadGuard<'_, T>> {
+        let level = self.level.checked_lock()?.shared();
+        Ok(map_guard(self.inner.read(), |guard| RwLockReadGuard {
+            inner: guard,
+            lock: self,
+            _level: level,
+        })?)
+    }
+
+    /// Like [`Self::write`], but returns [`CheckedLockError::Violation`](crate::CheckedLockError)
+    /// instead of reporting a lock hierarchy violation to the violation handler. In that case the
+    /// underlying lock is not touched, so the caller may back off.
+    #[track_caller]
+    pub fn checked_write(&self) -> CheckedLockResult<RwLockWriteGuard<'_, T>> {
+        let level = self.level.checked_lock()?;
+        Ok(map_guard(self.inner.write(), |guard| RwLockWriteGuard {
+            inner: guard,
+            lock: self,
+            _level: level,
+        })?)
+    }
+
+    /// See [std::sync::RwLock::try_read]
+    ///
+    /// A non blocking attempt can not deadlock, so it may happen out of hierarchy order. Once
+    /// acquired, the lock is tracked like any other and later acquisitions are checked against it.
+    #[track_caller]
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.level.lock_unchecked().shared();
+        map_try_guard(self.inner.try_read(), |guard| RwLockReadGuard {
+            inner: guard,
+            lock: self,
+            _level: level,
+        })
+    }
+
+    /// See [std::sync::RwLock::try_write]
+    ///
+    /// A non blocking attempt can not deadlock, so it may happen out of hierarchy order. Once
+    /// acquired, the lock is tracked like any other and later acquisitions are checked against it.
+    #[track_caller]
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.level.lock_unchecked();
+        map_try_guard(self.inner.try_write(), |guard| RwLockWriteGuard {
+            inner: guard,
+            lock: self,
+            _level: level,
+        })
+    }
+
+    /// Whether the current thread holds this lock for reading. Always `false` if lock hierarchies
+    /// are not checked, e.g. in release builds, so only use it for assertions in debug builds.
+    pub fn is_read_held(&self) -> bool {
+        self.level.is_held(true)
+    }
+
+    /// Whether the current thread holds this lock for writing. Always `false` if lock hierarchies
+    /// are not checked, e.g. in release builds, so only use it for assertions in debug builds.
+    pub fn is_write_held(&self) -> bool {
+        self.level.is_held(false)
+    }
+
+    /// Panics in debug builds if the current thread does not hold this lock for reading.
+    #[track_caller]
+    pub fn assert_read_held(&self) {
+        self.level.assert_held(true)
+    }
+
+    /// Panics in debug builds if the current thread does not hold this lock for writing.
+    #[track_caller]
+    pub fn assert_write_held(&self) {
+        self.level.assert_held(false)
+    }
+
     /// See [std::sync::RwLock::get_mut]
     pub fn get_mut(&mut self) -> LockResult<&mut T> {
         // No need to check hierarchy, this does not lock
@@ -78,6 +189,12 @@ impl<T> RwLock<T> {
     }
 }
 
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
 impl<T> From<T> for RwLock<T> {
     /// Creates a new mutex in an unlocked state ready for use.
     /// This is equivalent to [`RwLock::new`].
@@ -86,9 +203,236 @@ impl<T> From<T> for RwLock<T> {
     }
 }
 
+/// A [`RwLock`] which also offers upgradable reads. Read mostly code which occasionally needs to
+/// mutate can acquire an upgradable read lock, which is upgraded to a write lock without releasing
+/// it in between.
+///
+/// Writers and upgradable readers take an additional mutex, so at most one of them accesses the
+/// value at a time. This costs every write an additional lock, also in release builds, so prefer
+/// a plain [`RwLock`] unless reads need to be upgraded.
+///
+/// ```
+/// use lock_hierarchy::{RwLockUpgradableReadGuard, UpgradableRwLock};
+///
+/// let cache = UpgradableRwLock::new(Vec::new());
+///
+/// let entries = cache.upgradable_read().unwrap();
+/// if entries.is_empty() {
+///     // No other writer may fill the cache in between
+///     let mut entries = RwLockUpgradableReadGuard::upgrade(entries);
+///     entries.push(42);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct UpgradableRwLock<T> {
+    lock: RwLock<T>,
+    /// Held by writers and upgradable readers. Readers ignore it.
+    upgradable: std::sync::Mutex<()>,
+}
+
+impl<T> UpgradableRwLock<T> {
+    /// Creates a lock with level 0, see [`RwLock::new`].
+    pub fn new(t: T) -> Self {
+        Self::with_level(t, 0)
+    }
+
+    /// Creates a lock and assigns it a level in the lock hierarchy, see [`RwLock::with_level`].
+    pub fn with_level(t: T, level: impl LockLevel) -> Self {
+        Self::from_lock(RwLock::with_level(t, level))
+    }
+
+    /// Like [`Self::with_level`], but also names the lock, see [`RwLock::with_name_and_level`].
+    pub fn with_name_and_level(t: T, name: &'static str, level: impl LockLevel) -> Self {
+        Self::from_lock(RwLock::with_name_and_level(t, name, level))
+    }
+
+    fn from_lock(lock: RwLock<T>) -> Self {
+        UpgradableRwLock {
+            lock,
+            upgradable: std::sync::Mutex::new(()),
+        }
+    }
+
+    /// See [`RwLock::read`]. Upgradable readers do not keep readers out.
+    #[track_caller]
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        self.lock.read()
+    }
+
+    /// See [`RwLock::write`]
+    #[track_caller]
+    pub fn write(&self) -> LockResult<UpgradableRwLockWriteGuard<'_, T>> {
+        let level = self.lock.level.lock();
+        let upgradable = Upgradable::lock(&self.upgradable);
+        map_guard(self.lock.inner.write(), |guard| {
+            UpgradableRwLockWriteGuard {
+                inner: RwLockWriteGuard {
+                    inner: guard,
+                    lock: &self.lock,
+                    _level: level,
+                },
+                upgradable,
+            }
+        })
+    }
+
+    /// Acquires the lock for reading, but exclusive with writers and other upgradable readers. The
+    /// guard may be upgraded to a write guard by [`RwLockUpgradableReadGuard::upgrade`], without
+    /// other writers getting in between. Like a read lock, it counts as held for reading.
+    #[track_caller]
+    pub fn upgradable_read(&self) -> LockResult<RwLockUpgradableReadGuard<'_, T>> {
+        let level = self.lock.level.lock().shared();
+        let upgradable = Upgradable::lock(&self.upgradable);
+        map_guard(self.lock.inner.read(), |guard| RwLockUpgradableReadGuard {
+            inner: guard,
+            upgradable,
+            lock: &self.lock,
+            level,
+        })
+    }
+
+    /// See [`RwLock::checked_read`]
+    #[track_caller]
+    pub fn checked_read(&self) -> CheckedLockResult<RwLockReadGuard<'_, T>> {
+        self.lock.checked_read()
+    }
+
+    /// See [`RwLock::checked_write`]
+    #[track_caller]
+    pub fn checked_write(&self) -> CheckedLockResult<UpgradableRwLockWriteGuard<'_, T>> {
+        let level = self.lock.level.checked_lock()?;
+        let upgradable = Upgradable::lock(&self.upgradable);
+        Ok(map_guard(self.lock.inner.write(), |guard| {
+            UpgradableRwLockWriteGuard {
+                inner: RwLockWriteGuard {
+                    inner: guard,
+                    lock: &self.lock,
+                    _level: level,
+                },
+                upgradable,
+            }
+        })?)
+    }
+
+    /// See [`RwLock::try_read`]
+    #[track_caller]
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        self.lock.try_read()
+    }
+
+    /// See [`RwLock::try_write`]
+    #[track_caller]
+    pub fn try_write(&self) -> TryLockResult<UpgradableRwLockWriteGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.lock.level.lock_unchecked();
+        let upgradable = Upgradable::try_lock(&self.upgradable).ok_or(TryLockError::WouldBlock)?;
+        map_try_guard(self.lock.inner.try_write(), |guard| {
+            UpgradableRwLockWriteGuard {
+                inner: RwLockWriteGuard {
+                    inner: guard,
+                    lock: &self.lock,
+                    _level: level,
+                },
+                upgradable,
+            }
+        })
+    }
+
+    /// Like [`Self::upgradable_read`], but does not block. See [`RwLock::try_read`].
+    #[track_caller]
+    pub fn try_upgradable_read(&self) -> TryLockResult<RwLockUpgradableReadGuard<'_, T>> {
+        // Released again if the attempt fails
+        let level = self.lock.level.lock_unchecked().shared();
+        let upgradable = Upgradable::try_lock(&self.upgradable).ok_or(TryLockError::WouldBlock)?;
+        map_try_guard(self.lock.inner.try_read(), |guard| {
+            RwLockUpgradableReadGuard {
+                inner: guard,
+                upgradable,
+                lock: &self.lock,
+                level,
+            }
+        })
+    }
+
+    /// See [`RwLock::is_read_held`]. Also `true` for upgradable reads.
+    pub fn is_read_held(&self) -> bool {
+        self.lock.is_read_held()
+    }
+
+    /// See [`RwLock::is_write_held`]
+    pub fn is_write_held(&self) -> bool {
+        self.lock.is_write_held()
+    }
+
+    /// See [`RwLock::assert_read_held`]
+    #[track_caller]
+    pub fn assert_read_held(&self) {
+        self.lock.assert_read_held()
+    }
+
+    /// See [`RwLock::assert_write_held`]
+    #[track_caller]
+    pub fn assert_write_held(&self) {
+        self.lock.assert_write_held()
+    }
+
+    /// See [std::sync::RwLock::get_mut]
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.lock.get_mut()
+    }
+
+    /// See [std::sync::RwLock::into_inner]
+    pub fn into_inner(self) -> LockResult<T> {
+        self.lock.into_inner()
+    }
+}
+
+impl<T: Default> Default for UpgradableRwLock<T> {
+    fn default() -> Self {
+        UpgradableRwLock::new(T::default())
+    }
+}
+
+impl<T> From<T> for UpgradableRwLock<T> {
+    /// Creates a new lock in an unlocked state ready for use.
+    /// This is equivalent to [`UpgradableRwLock::new`].
+    fn from(value: T) -> Self {
+        UpgradableRwLock::new(value)
+    }
+}
+
+/// Held by writers and upgradable readers of an [`UpgradableRwLock`].
+struct Upgradable<'a> {
+    mutex: &'a std::sync::Mutex<()>,
+    _guard: std::sync::MutexGuard<'a, ()>,
+}
+
+impl<'a> Upgradable<'a> {
+    fn lock(mutex: &'a std::sync::Mutex<()>) -> Self {
+        Upgradable {
+            mutex,
+            // Protects no data, poisoning is reported by the `RwLock`
+            _guard: mutex.lock().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    fn try_lock(mutex: &'a std::sync::Mutex<()>) -> Option<Self> {
+        let guard = match mutex.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(Upgradable {
+            mutex,
+            _guard: guard,
+        })
+    }
+}
+
 pub struct RwLockReadGuard<'a, T> {
     inner: std::sync::RwLockReadGuard<'a, T>,
-    _level: LevelGuard,
+    lock: &'a RwLock<T>,
+    _level: LevelGuard<'a>,
 }
 
 impl<T: Debug> Debug for RwLockReadGuard<'_, T> {
@@ -111,9 +455,210 @@ impl<T> Deref for RwLockReadGuard<'_, T> {
     }
 }
 
+impl<'a, T> RwLockReadGuard<'a, T> {
+    /// Releases the lock while `f` runs. Its level is removed from the stack of held locks for
+    /// that time, too. Afterwards the lock is acquired for reading again, after checking its level
+    /// against the locks held at that time. See
+    /// [`MutexGuard::unlocked`](crate::MutexGuard::unlocked).
+    ///
+    /// Other threads may change the value in the meantime. If one of them poisons the lock, it is
+    /// acquired again anyway and the poisoning is not reported.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    #[track_caller]
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        let location = Location::caller();
+        let RwLockReadGuard {
+            inner,
+            lock,
+            _level,
+        } = guard;
+        crate::unlocked(
+            inner,
+            || lock.relock_read(),
+            || _level.released_at(location, f),
+        )
+    }
+
+    /// Projects the guard to a part of the protected value, e.g. a field. The lock stays acquired
+    /// for reading, and its level acquired, until the mapped guard is dropped.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    pub fn map<U: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&T) -> &U,
+    ) -> MappedRwLockReadGuard<'a, T, U> {
+        let data = NonNull::from(f(&guard));
+        MappedRwLockReadGuard { data, guard }
+    }
+
+    /// Like [`Self::map`], but `f` may fail, in which case the original guard is returned.
+    pub fn try_map<U: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&T) -> Option<&U>,
+    ) -> Result<MappedRwLockReadGuard<'a, T, U>, Self> {
+        match f(&guard).map(NonNull::from) {
+            Some(data) => Ok(MappedRwLockReadGuard { data, guard }),
+            None => Err(guard),
+        }
+    }
+}
+
+/// A [`RwLockReadGuard`] projected to a part `U` of the protected value `T` by
+/// [`RwLockReadGuard::map`].
+pub struct MappedRwLockReadGuard<'a, T, U: ?Sized> {
+    /// Points into the value protected by `guard`.
+    data: NonNull<U>,
+    /// Keeps the lock and its level acquired.
+    guard: RwLockReadGuard<'a, T>,
+}
+
+// SAFETY: Sharing the guard only allows for shared access to `U`, and to nothing else.
+unsafe impl<T: Sync, U: ?Sized + Sync> Sync for MappedRwLockReadGuard<'_, T, U> {}
+
+impl<'a, T, U: ?Sized> MappedRwLockReadGuard<'a, T, U> {
+    /// Projects the guard further, see [`RwLockReadGuard::map`].
+    pub fn map<V: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&U) -> &V,
+    ) -> MappedRwLockReadGuard<'a, T, V> {
+        let data = NonNull::from(f(&guard));
+        MappedRwLockReadGuard {
+            data,
+            guard: guard.guard,
+        }
+    }
+
+    /// Like [`Self::map`], but `f` may fail, in which case the original guard is returned.
+    pub fn try_map<V: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&U) -> Option<&V>,
+    ) -> Result<MappedRwLockReadGuard<'a, T, V>, Self> {
+        match f(&guard).map(NonNull::from) {
+            Some(data) => Ok(MappedRwLockReadGuard {
+                data,
+                guard: guard.guard,
+            }),
+            None => Err(guard),
+        }
+    }
+}
+
+impl<T, U: ?Sized + Debug> Debug for MappedRwLockReadGuard<'_, T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T, U: ?Sized + Display> Display for MappedRwLockReadGuard<'_, T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T, U: ?Sized> Deref for MappedRwLockReadGuard<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        // SAFETY: `data` has been borrowed from `guard`, which is kept alive
+        unsafe { self.data.as_ref() }
+    }
+}
+
+/// Read access to the value of an [`UpgradableRwLock`], which may be upgraded to write access.
+/// See [`UpgradableRwLock::upgradable_read`].
+pub struct RwLockUpgradableReadGuard<'a, T> {
+    inner: std::sync::RwLockReadGuard<'a, T>,
+    /// Keeps writers and other upgradable readers out, until the guard is dropped.
+    upgradable: Upgradable<'a>,
+    lock: &'a RwLock<T>,
+    level: LevelGuard<'a>,
+}
+
+impl<'a, T> RwLockUpgradableReadGuard<'a, T> {
+    /// Releases the lock while `f` runs, like [`RwLockReadGuard::unlocked`]. Writers and other
+    /// upgradable readers may acquire it in the meantime. If one of them poisons the lock, the
+    /// poisoning is not reported.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    #[track_caller]
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        let location = Location::caller();
+        let RwLockUpgradableReadGuard {
+            inner,
+            upgradable,
+            lock,
+            level,
+        } = guard;
+        let mutex = upgradable.mutex;
+        // Released in the same order as by dropping the guard, acquired again in reverse order
+        crate::unlocked(
+            inner,
+            || lock.relock_read(),
+            || {
+                crate::unlocked(
+                    upgradable,
+                    || Upgradable::lock(mutex),
+                    || level.released_at(location, f),
+                )
+            },
+        )
+    }
+
+    /// Upgrades to write access, blocking until all readers are gone. No writer gets in between,
+    /// so the value is still the one read so far. The level stays on the stack of held locks in
+    /// place and is not checked again.
+    ///
+    /// Waiting for the readers may deadlock if the current thread holds locks it acquired after
+    /// this one. Upgrade before acquiring lower locks.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    pub fn upgrade(guard: Self) -> UpgradableRwLockWriteGuard<'a, T> {
+        let RwLockUpgradableReadGuard {
+            inner,
+            upgradable,
+            lock,
+            level,
+        } = guard;
+        drop(inner);
+        // Writers which poisoned the lock did so before the upgradable guard has been acquired,
+        // which reported it already.
+        let inner = lock.relock_write();
+        UpgradableRwLockWriteGuard {
+            inner: RwLockWriteGuard {
+                inner,
+                lock,
+                _level: level.exclusive(),
+            },
+            upgradable,
+        }
+    }
+}
+
+impl<T: Debug> Debug for RwLockUpgradableReadGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.inner, f)
+    }
+}
+
+impl<T: Display> Display for RwLockUpgradableReadGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.inner, f)
+    }
+}
+
+impl<T> Deref for RwLockUpgradableReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.inner.deref()
+    }
+}
+
 pub struct RwLockWriteGuard<'a, T> {
     inner: std::sync::RwLockWriteGuard<'a, T>,
-    _level: LevelGuard,
+    lock: &'a RwLock<T>,
+    _level: LevelGuard<'a>,
 }
 
 impl<T: Debug> Debug for RwLockWriteGuard<'_, T> {
@@ -142,11 +687,441 @@ impl<T> DerefMut for RwLockWriteGuard<'_, T> {
     }
 }
 
+impl<'a, T> RwLockWriteGuard<'a, T> {
+    /// Releases the lock while `f` runs, like [`RwLockReadGuard::unlocked`]. Afterwards the lock is
+    /// acquired for writing again. Other threads may change the value in the meantime. If one of
+    /// them poisons the lock, the poisoning is not reported.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    #[track_caller]
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        let location = Location::caller();
+        let RwLockWriteGuard {
+            inner,
+            lock,
+            _level,
+        } = guard;
+        crate::unlocked(
+            inner,
+            || lock.relock_write(),
+            || _level.released_at(location, f),
+        )
+    }
+
+    /// Turns write access into read access. No writer gets in between, so the value is still the
+    /// one written so far. The level stays on the stack of held locks in place and is not checked
+    /// again.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    pub fn downgrade(guard: Self) -> RwLockReadGuard<'a, T> {
+        let RwLockWriteGuard {
+            inner,
+            lock,
+            _level: level,
+        } = guard;
+        let inner = std::sync::RwLockWriteGuard::downgrade(inner);
+        RwLockReadGuard {
+            inner,
+            lock,
+            _level: level.shared(),
+        }
+    }
+
+    /// Projects the guard to a part of the protected value, e.g. a field. The lock stays acquired
+    /// for writing, and its level acquired, until the mapped guard is dropped.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    pub fn map<U: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedRwLockWriteGuard<'a, T, U> {
+        let data = NonNull::from(f(&mut guard));
+        MappedRwLockWriteGuard {
+            data,
+            guard,
+            _data: PhantomData,
+        }
+    }
+
+    /// Like [`Self::map`], but `f` may fail, in which case the original guard is returned.
+    pub fn try_map<U: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedRwLockWriteGuard<'a, T, U>, Self> {
+        match f(&mut guard).map(NonNull::from) {
+            Some(data) => Ok(MappedRwLockWriteGuard {
+                data,
+                guard,
+                _data: PhantomData,
+            }),
+            None => Err(guard),
+        }
+    }
+}
+
+/// Write access to the value of an [`UpgradableRwLock`]. Like a [`RwLockWriteGuard`], but also
+/// keeps upgradable readers out until dropped, so relocking in [`Self::unlocked`] can not get in
+/// between the upgrade of another thread.
+pub struct UpgradableRwLockWriteGuard<'a, T> {
+    /// Released before `upgradable`.
+    inner: RwLockWriteGuard<'a, T>,
+    upgradable: Upgradable<'a>,
+}
+
+impl<'a, T> UpgradableRwLockWriteGuard<'a, T> {
+    /// See [`RwLockWriteGuard::unlocked`]. Upgradable readers may acquire the lock in the
+    /// meantime, too.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    #[track_caller]
+    pub fn unlocked<R>(guard: &mut Self, f: impl FnOnce() -> R) -> R {
+        let location = Location::caller();
+        let UpgradableRwLockWriteGuard {
+            inner:
+                RwLockWriteGuard {
+                    inner,
+                    lock,
+                    _level,
+                },
+            upgradable,
+        } = guard;
+        let mutex = upgradable.mutex;
+        // Released in the same order as by dropping the guard, acquired again in reverse order
+        crate::unlocked(
+            inner,
+            || lock.relock_write(),
+            || {
+                crate::unlocked(
+                    upgradable,
+                    || Upgradable::lock(mutex),
+                    || _level.released_at(location, f),
+                )
+            },
+        )
+    }
+
+    /// See [`RwLockWriteGuard::downgrade`]. Upgradable readers may acquire the lock afterwards.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    pub fn downgrade(guard: Self) -> RwLockReadGuard<'a, T> {
+        RwLockWriteGuard::downgrade(guard.inner)
+    }
+
+    /// See [`RwLockWriteGuard::map`]. Upgradable readers wait for the write lock held by the
+    /// mapped guard, so they need not be kept out separately.
+    ///
+    /// This is an associated function, so it does not shadow a method of `T`.
+    pub fn map<U: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedRwLockWriteGuard<'a, T, U> {
+        RwLockWriteGuard::map(guard.inner, f)
+    }
+
+    /// Like [`Self::map`], but `f` may fail, in which case the original guard is returned.
+    pub fn try_map<U: ?Sized>(
+        guard: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedRwLockWriteGuard<'a, T, U>, Self> {
+        let UpgradableRwLockWriteGuard { inner, upgradable } = guard;
+        RwLockWriteGuard::try_map(inner, f)
+            .map_err(|inner| UpgradableRwLockWriteGuard { inner, upgradable })
+    }
+}
+
+impl<T: Debug> Debug for UpgradableRwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.inner, f)
+    }
+}
+
+impl<T: Display> Display for UpgradableRwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.inner, f)
+    }
+}
+
+impl<T> Deref for UpgradableRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.inner.deref()
+    }
+}
+
+impl<T> DerefMut for UpgradableRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.deref_mut()
+    }
+}
+
+/// A [`RwLockWriteGuard`] projected to a part `U` of the protected value `T` by
+/// [`RwLockWriteGuard::map`].
+pub struct MappedRwLockWriteGuard<'a, T, U: ?Sized> {
+    /// Points into the value protected by `guard`.
+    data: NonNull<U>,
+    /// Keeps the lock and its level acquired.
+    guard: RwLockWriteGuard<'a, T>,
+    /// Invariant in `U`, like a mutable reference.
+    _data: PhantomData<&'a mut U>,
+}
+
+// SAFETY: Sharing the guard only allows for shared access to `U`, and to nothing else.
+unsafe impl<T: Sync, U: ?Sized + Sync> Sync for MappedRwLockWriteGuard<'_, T, U> {}
+
+impl<'a, T, U: ?Sized> MappedRwLockWriteGuard<'a, T, U> {
+    /// Projects the guard further, see [`RwLockWriteGuard::map`].
+    pub fn map<V: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut U) -> &mut V,
+    ) -> MappedRwLockWriteGuard<'a, T, V> {
+        let data = NonNull::from(f(&mut guard));
+        MappedRwLockWriteGuard {
+            data,
+            guard: guard.guard,
+            _data: PhantomData,
+        }
+    }
+
+    /// Like [`Self::map`], but `f` may fail, in which case the original guard is returned.
+    pub fn try_map<V: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut U) -> Option<&mut V>,
+    ) -> Result<MappedRwLockWriteGuard<'a, T, V>, Self> {
+        match f(&mut guard).map(NonNull::from) {
+            Some(data) => Ok(MappedRwLockWriteGuard {
+                data,
+                guard: guard.guard,
+                _data: PhantomData,
+            }),
+            None => Err(guard),
+        }
+    }
+}
+
+impl<T, U: ?Sized + Debug> Debug for MappedRwLockWriteGuard<'_, T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T, U: ?Sized + Display> Display for MappedRwLockWriteGuard<'_, T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.deref(), f)
+    }
+}
+
+impl<T, U: ?Sized> Deref for MappedRwLockWriteGuard<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        // SAFETY: `data` has been borrowed mutably from `guard`, which is not used otherwise
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<T, U: ?Sized> DerefMut for MappedRwLockWriteGuard<'_, T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        // SAFETY: `data` has been borrowed mutably from `guard`, which is not used otherwise
+        unsafe { self.data.as_mut() }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{hint::black_box, sync::Arc, thread};
+    use std::{
+        hint::black_box,
+        sync::{Arc, TryLockError},
+        thread,
+    };
 
     use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+    use crate::CheckedLockError;
+
+    #[test]
+    fn map_guards_to_fields() {
+        let lock = RwLock::new((1, String::from("one")));
+        {
+            let mut name = RwLockWriteGuard::map(lock.write().unwrap(), |(_, name)| name);
+            name.push('!');
+            #[cfg(lock_check)]
+            lock.assert_write_held();
+        }
+        let name = RwLockReadGuard::map(lock.read().unwrap(), |(_, name)| name.as_str());
+        let name = MappedRwLockReadGuard::try_map(name, |name| name.strip_suffix('!')).unwrap();
+        assert_eq!("one", &*name);
+        #[cfg(lock_check)]
+        lock.assert_read_held();
+        drop(name);
+        assert!(RwLockReadGuard::try_map(lock.read().unwrap(), |_| None::<&()>).is_err());
+    }
+
+    #[test]
+    #[cfg(not(lock_check))]
+    fn write_guard_is_small_without_checks() {
+        // Only the lock itself is added, to acquire it again in `unlocked`
+        assert_eq!(
+            size_of::<std::sync::RwLockWriteGuard<'_, u8>>() + size_of::<&RwLock<u8>>(),
+            size_of::<RwLockWriteGuard<'_, u8>>()
+        );
+    }
+
+    #[test]
+    fn map_upgradable_write_guard() {
+        let lock = UpgradableRwLock::new((1, String::from("one")));
+        let mut name = UpgradableRwLockWriteGuard::map(lock.write().unwrap(), |(_, name)| name);
+        name.push('!');
+        assert!(lock.try_upgradable_read().is_err());
+        drop(name);
+        let guard = lock.write().unwrap();
+        let guard = UpgradableRwLockWriteGuard::try_map(guard, |_| None::<&mut ()>).unwrap_err();
+        assert_eq!("one!", guard.1);
+    }
+
+    #[test]
+    fn upgradable_read_excludes_writers_but_not_readers() {
+        let lock = UpgradableRwLock::new(1);
+        let guard = lock.upgradable_read().unwrap();
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(1, *lock.try_read().unwrap());
+                assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+                assert!(matches!(
+                    lock.try_upgradable_read(),
+                    Err(TryLockError::WouldBlock)
+                ));
+            });
+        });
+        let mut guard = RwLockUpgradableReadGuard::upgrade(guard);
+        *guard += 1;
+        let guard = UpgradableRwLockWriteGuard::downgrade(guard);
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(2, *lock.try_read().unwrap());
+                assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+            });
+        });
+        assert_eq!(2, *guard);
+        drop(guard);
+        assert!(lock.try_upgradable_read().is_ok());
+    }
+
+    #[test]
+    fn unlocked_releases_read_and_write_locks() {
+        let lock = UpgradableRwLock::with_level(1, 1);
+
+        let mut guard = lock.write().unwrap();
+        UpgradableRwLockWriteGuard::unlocked(&mut guard, || {
+            #[cfg(lock_check)]
+            assert!(!lock.is_write_held());
+            thread::scope(|s| {
+                s.spawn(|| *lock.upgradable_read().unwrap() + 1);
+                s.spawn(|| *lock.write().unwrap() += 1);
+            });
+        });
+        #[cfg(lock_check)]
+        lock.assert_write_held();
+        *guard += 1;
+        drop(guard);
+
+        let mut guard = lock.read().unwrap();
+        RwLockReadGuard::unlocked(&mut guard, || {
+            thread::scope(|s| {
+                s.spawn(|| *lock.write().unwrap() += 1);
+            });
+        });
+        #[cfg(lock_check)]
+        lock.assert_read_held();
+        assert_eq!(4, *guard);
+        drop(guard);
+
+        let mut guard = lock.upgradable_read().unwrap();
+        RwLockUpgradableReadGuard::unlocked(&mut guard, || {
+            thread::scope(|s| {
+                s.spawn(|| *lock.write().unwrap() += 1);
+            });
+        });
+        assert_eq!(5, *RwLockUpgradableReadGuard::upgrade(guard));
+    }
+
+    #[test]
+    fn unlock_and_downgrade_plain_write_guard() {
+        let lock = RwLock::new(1);
+
+        let mut guard = lock.write().unwrap();
+        RwLockWriteGuard::unlocked(&mut guard, || {
+            thread::scope(|s| {
+                s.spawn(|| *lock.write().unwrap() += 1);
+            });
+        });
+        *guard += 1;
+        let guard = RwLockWriteGuard::downgrade(guard);
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(3, *lock.try_read().unwrap());
+                assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+            });
+        });
+        #[cfg(lock_check)]
+        lock.assert_read_held();
+        drop(guard);
+        assert!(lock.try_write().is_ok());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn upgrade_and_downgrade_keep_the_level() {
+        let lock = UpgradableRwLock::with_level((), 2);
+        let lower = RwLock::with_level((), 1);
+
+        let guard = lock.upgradable_read().unwrap();
+        lock.assert_read_held();
+        let guard = RwLockUpgradableReadGuard::upgrade(guard);
+        lock.assert_write_held();
+        let guard = UpgradableRwLockWriteGuard::downgrade(guard);
+        lock.assert_read_held();
+        assert_eq!(1, crate::held_lock_count());
+        // Fine, 1 is lower than 2
+        let _lower = lower.write().unwrap();
+        drop(guard);
+        assert_eq!(1, crate::held_lock_count());
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn upgradable_read_is_checked() {
+        assert_eq!(
+            catch_violation(|| {
+                let lock = UpgradableRwLock::with_level((), 2);
+                let lower = RwLock::with_level((), 1);
+
+                let _lower = lower.read().unwrap();
+                // Must panic, 2 is higher than 1
+                let _guard = lock.upgradable_read();
+            }),
+            (Some(2), vec![1])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn is_read_or_write_held() {
+        let lock = RwLock::new(());
+        {
+            let _guard = lock.read().unwrap();
+            lock.assert_read_held();
+            assert!(!lock.is_write_held());
+        }
+        {
+            let _guard = lock.write().unwrap();
+            lock.assert_write_held();
+            assert!(!lock.is_read_held());
+        }
+        assert!(!lock.is_read_held());
+        assert!(!lock.is_write_held());
+    }
 
     #[test]
     fn acquire_resource() {
@@ -185,7 +1160,7 @@ mod tests {
         thread.join().unwrap();
     }
 
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn poisoned_lock() -> RwLock<()> {
         let mutex = RwLock::new(());
         std::panic::catch_unwind(|| {
@@ -197,84 +1172,181 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn poisoned_read_lock() {
-        let mutex = poisoned_lock();
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = poisoned_lock();
 
-        let _guard_a = mutex.read().unwrap_err().into_inner();
-        let _guard_b = mutex.read();
+                let _guard_a = mutex.read().unwrap_err().into_inner();
+                let _guard_b = mutex.read();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn poisoned_write_lock() {
-        let mutex = poisoned_lock();
+        assert_eq!(
+            catch_violation(|| {
+                let mutex = poisoned_lock();
 
-        let _guard_a = mutex.write().unwrap_err().into_inner();
-        let _guard_b = mutex.write();
+                let _guard_a = mutex.write().unwrap_err().into_inner();
+                let _guard_b = mutex.write();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn self_deadlock_write() {
-        // This ensures that the level is locked in RwLock::write before locking the std lock which might otherwise cause a deadlock
-        let mutex = RwLock::new(());
-        let _guard = mutex.read().unwrap();
-        let _guard = mutex.write().unwrap();
+        assert_eq!(
+            catch_violation(|| {
+                // This ensures that the level is locked in RwLock::write before locking the std lock which might otherwise cause a deadlock
+                let mutex = RwLock::new(());
+                let _guard = mutex.read().unwrap();
+                let _guard = mutex.write().unwrap();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[should_panic(
-        expected = "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a violation of lock hierarchies which could lead to deadlocks."
-    )]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn self_deadlock_read() {
-        // This ensures that the level is locked in RwLock::read before locking the std lock which might otherwise cause an unchecked deadlock
-        let mutex = RwLock::new(());
-        let _guard = mutex.read().unwrap();
-        let _guard = mutex.read().unwrap();
+        assert_eq!(
+            catch_violation(|| {
+                // This ensures that the level is locked in RwLock::read before locking the std lock which might otherwise cause an unchecked deadlock
+                let mutex = RwLock::new(());
+                let _guard = mutex.read().unwrap();
+                let _guard = mutex.read().unwrap();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn correct_level_locked() {
         let mutex = RwLock::with_level((), 1);
         let guard = mutex.read().unwrap();
-        assert_eq!(guard._level.level, 1);
+        assert_eq!(guard._level.level.level.as_integer(), Some(1));
         drop(guard);
         let guard = mutex.write().unwrap();
-        assert_eq!(guard._level.level, 1);
+        assert_eq!(guard._level.level.level.as_integer(), Some(1));
         drop(guard);
 
         let mutex = RwLock::new(());
         let guard = mutex.read().unwrap();
-        assert_eq!(guard._level.level, 0);
+        assert_eq!(guard._level.level.level.as_integer(), Some(0));
         drop(guard);
         let guard = mutex.write().unwrap();
-        assert_eq!(guard._level.level, 0);
+        assert_eq!(guard._level.level.level.as_integer(), Some(0));
+        drop(guard);
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn checked_lock_violation() {
+        let mutex_a = RwLock::new(());
+        let mutex_b = RwLock::new(());
+        let _guard_a = mutex_a.read().unwrap();
+        assert!(matches!(
+            mutex_b.checked_read(),
+            Err(CheckedLockError::Violation(_))
+        ));
+        // Must not deadlock, the lock is not touched
+        assert!(matches!(
+            mutex_a.checked_write(),
+            Err(CheckedLockError::Violation(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn violation_reports_locations() {
+        let mutex_a = RwLock::new(());
+        let mutex_b = RwLock::new(());
+        let line = line!() + 1;
+        let _guard_a = mutex_a.try_write().unwrap();
+        let Err(CheckedLockError::Violation(violation)) = mutex_b.checked_read() else {
+            panic!("Expected a violation");
+        };
+        let held = violation.held()[0].location();
+        assert_eq!((file!(), line), (held.file(), held.line()));
+        let attempted = violation.attempted().unwrap().location();
+        assert_eq!((file!(), line + 1), (attempted.file(), attempted.line()));
+    }
+
+    #[test]
+    fn checked_lock_poisoned() {
+        let mutex = RwLock::new(());
+        std::panic::catch_unwind(|| {
+            let _guard = mutex.write();
+            panic!("lock is poisoned now");
+        })
+        .unwrap_err();
+
+        assert!(matches!(
+            mutex.checked_read(),
+            Err(CheckedLockError::Poisoned(_))
+        ));
+        assert!(matches!(
+            mutex.checked_write(),
+            Err(CheckedLockError::Poisoned(_))
+        ));
+    }
+
+    #[test]
+    fn try_lock_out_of_order() {
+        let mutex_a = RwLock::new(());
+        let mutex_b = RwLock::with_level((), 1);
+        let mutex_c = RwLock::with_level((), 2);
+        let _guard_a = mutex_a.read().unwrap();
+        // Fine, non blocking attempts can not deadlock
+        let _guard_b = mutex_b.try_read().unwrap();
+        let _guard_c = mutex_c.try_write().unwrap();
+    }
+
+    #[test]
+    fn try_lock_would_block() {
+        let mutex = RwLock::new(());
+        let guard = mutex.write().unwrap();
+        assert!(matches!(mutex.try_read(), Err(TryLockError::WouldBlock)));
+        assert!(matches!(mutex.try_write(), Err(TryLockError::WouldBlock)));
         drop(guard);
+
+        let _guard = mutex.read().unwrap();
+        assert!(matches!(mutex.try_write(), Err(TryLockError::WouldBlock)));
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn lock_after_try_lock() {
+        assert_eq!(
+            catch_violation(|| {
+                let mutex_a = RwLock::new(());
+                let mutex_b = RwLock::new(());
+                let _guard_a = mutex_a.try_read().unwrap();
+                let _guard_b = mutex_b.write().unwrap();
+            }),
+            (Some(0), vec![0])
+        );
     }
 
     #[test]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn created_by_default_impl_should_be_level_0() {
         let mutex = RwLock::<()>::default();
-        assert_eq!(mutex.level.level, 0);
+        assert_eq!(mutex.level.level.as_integer(), Some(0));
     }
 
     #[test]
-    #[cfg(debug_assertions)]
+    #[cfg(lock_check)]
     fn mutex_created_by_from_impl_should_be_level_0() {
         let mutex: RwLock<u8> = 42.into();
-        assert_eq!(mutex.level.level, 0);
+        assert_eq!(mutex.level.level.as_integer(), Some(0));
     }
 }
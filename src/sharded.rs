@@ -0,0 +1,258 @@
+#[cfg(lock_check)]
+use std::panic::Location;
+use std::sync::{LockResult, PoisonError, TryLockResult};
+
+#[cfg(lock_check)]
+use crate::level::Level;
+use crate::{LockLevel, Mutex, MutexGuard};
+
+/// A collection of mutexes, e.g. the shards of a map, which share one level in the hierarchy.
+///
+/// Any single shard may be locked. Several shards may be held at once, as long as they are
+/// locked in ascending order of their indices, or all at once by [`Self::lock_all`]. Locking a
+/// shard with a lower index than a shard already held is reported as a violation in debug builds.
+///
+/// ```
+/// use lock_hierarchy::ShardedMutex;
+///
+/// let shards = ShardedMutex::with_level([0, 0, 0, 0], 3);
+///
+/// let mut first = shards.lock(1).unwrap();
+/// // Fine, 3 is a higher index than 1
+/// let mut second = shards.lock(3).unwrap();
+/// *first += 1;
+/// *second += 1;
+/// // Would panic, 2 is a lower index than 3
+/// // let _third = shards.lock(2).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct ShardedMutex<T> {
+    shards: Box<[Mutex<T>]>,
+    /// Shards are siblings, see `LockInfo::group`.
+    #[cfg(lock_check)]
+    group: usize,
+}
+
+impl<T> ShardedMutex<T> {
+    /// Creates a shard with level 0 for each value.
+    pub fn new(shards: impl IntoIterator<Item = T>) -> Self {
+        Self::from_shards(shards.into_iter().map(Mutex::new).collect())
+    }
+
+    /// Creates a shard for each value, all of them with the same level in the lock hierarchy.
+    pub fn with_level<L: LockLevel + Clone>(shards: impl IntoIterator<Item = T>, level: L) -> Self {
+        Self::from_shards(
+            shards
+                .into_iter()
+                .map(|t| Mutex::with_level(t, level.clone()))
+                .collect(),
+        )
+    }
+
+    /// Like [`Self::with_level`], but also names the shards. The name is part of every violation
+    /// report involving a shard.
+    pub fn with_name_and_level<L: LockLevel + Clone>(
+        shards: impl IntoIterator<Item = T>,
+        name: &'static str,
+        level: L,
+    ) -> Self {
+        Self::from_shards(
+            shards
+                .into_iter()
+                .map(|t| Mutex::with_name_and_level(t, name, level.clone()))
+                .collect(),
+        )
+    }
+
+    fn from_shards(shards: Box<[Mutex<T>]>) -> Self {
+        // Shards must be acquired in the order of their instances, so assign them by index
+        #[cfg(lock_check)]
+        for shard in shards.iter() {
+            shard.level.instance();
+        }
+        ShardedMutex {
+            shards,
+            #[cfg(lock_check)]
+            group: Level::group(),
+        }
+    }
+
+    /// Number of shards.
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Whether there are no shards at all.
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Locks the shard at `index`. Shards already held by the current thread must have lower
+    /// indices.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    #[track_caller]
+    pub fn lock(&self, index: usize) -> LockResult<MutexGuard<'_, T>> {
+        #[cfg(lock_check)]
+        {
+            self.shards[index].lock_in_group(Location::caller(), self.group)
+        }
+        #[cfg(not(lock_check))]
+        self.shards[index].lock()
+    }
+
+    /// See [`Mutex::try_lock`].
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    #[track_caller]
+    pub fn try_lock(&self, index: usize) -> TryLockResult<MutexGuard<'_, T>> {
+        #[cfg(lock_check)]
+        {
+            self.shards[index].try_lock_in_group(Location::caller(), self.group)
+        }
+        #[cfg(not(lock_check))]
+        self.shards[index].try_lock()
+    }
+
+    /// Locks all shards in ascending order and returns their guards. If any shard is poisoned, all
+    /// guards are returned within the [`PoisonError`].
+    #[track_caller]
+    pub fn lock_all(&self) -> LockResult<Vec<MutexGuard<'_, T>>> {
+        let mut poisoned = false;
+        let guards = (0..self.len())
+            .map(|index| {
+                self.lock(index).unwrap_or_else(|error| {
+                    poisoned = true;
+                    error.into_inner()
+                })
+            })
+            .collect();
+        if poisoned {
+            Err(PoisonError::new(guards))
+        } else {
+            Ok(guards)
+        }
+    }
+
+    /// See [`Mutex::get_mut`].
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn get_mut(&mut self, index: usize) -> LockResult<&mut T> {
+        self.shards[index].get_mut()
+    }
+
+    /// Values of all shards, in order of their indices. Poisoned shards are included.
+    pub fn into_inner(self) -> Vec<T> {
+        self.shards
+            .into_vec()
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+
+    #[test]
+    fn lock_shards_in_ascending_order() {
+        let shards = ShardedMutex::with_level([1, 2, 3], 5);
+        let lower = Mutex::with_level((), 4);
+
+        let first = shards.lock(0).unwrap();
+        let third = shards.lock(2).unwrap();
+        // Fine, 4 is lower than the shards
+        let _lower = lower.lock().unwrap();
+        assert_eq!((1, 3), (*first, *third));
+    }
+
+    #[test]
+    fn lock_all_shards() {
+        let shards = ShardedMutex::new(vec![1, 2, 3]);
+        {
+            let mut guards = shards.lock_all().unwrap();
+            *guards[1] += 10;
+        }
+        assert_eq!(vec![1, 12, 3], shards.into_inner());
+    }
+
+    #[test]
+    fn try_lock_and_lock_shards_in_ascending_order() {
+        let shards = ShardedMutex::with_level([1, 2, 3], 5);
+
+        let first = shards.try_lock(0).unwrap();
+        // Fine, 1 is a higher index than 0
+        let second = shards.lock(1).unwrap();
+        drop(first);
+        // Fine, non blocking attempts may happen in any order
+        let _first = shards.try_lock(0).unwrap();
+        let _third = shards.lock(2).unwrap();
+        assert_eq!(2, *second);
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn lock_shard_below_try_locked_shard() {
+        assert_eq!(
+            catch_violation(|| {
+                let shards = ShardedMutex::with_level([(), ()], 5);
+                let _second = shards.try_lock(1).unwrap();
+                // Must panic, 0 is a lower index than 1
+                let _first = shards.lock(0);
+            }),
+            (Some(5), vec![5])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn lock_shards_in_descending_order() {
+        assert_eq!(
+            catch_violation(|| {
+                let shards = ShardedMutex::with_level([(), ()], 5);
+                let _second = shards.lock(1).unwrap();
+                // Must panic, 0 is a lower index than 1
+                let _first = shards.lock(0);
+            }),
+            (Some(5), vec![5])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn lock_shard_twice() {
+        assert_eq!(
+            catch_violation(|| {
+                let shards = ShardedMutex::with_level([(), ()], 5);
+                let _first = shards.lock(0).unwrap();
+                // Must panic, this would deadlock
+                let _again = shards.lock(0);
+            }),
+            (Some(5), vec![5])
+        );
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn shards_of_different_collections_are_no_siblings() {
+        assert_eq!(
+            catch_violation(|| {
+                let shards_a = ShardedMutex::with_level([()], 5);
+                let shards_b = ShardedMutex::with_level([()], 5);
+                let _a = shards_a.lock(0).unwrap();
+                // Must panic, both have level 5
+                let _b = shards_b.lock(0);
+            }),
+            (Some(5), vec![5])
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Locks whose levels are types, so acquiring them out of order does not compile.
+//!
+//! Each level is a type implementing [`Level`]. [`LockAfter`] declares which levels may be
+//! acquired while holding which others, usually via [`lock_after!`](crate::lock_after). Acquiring
+//! a lock requires a [`LockToken`] for the level held most recently and yields a token for the
+//! level of the new lock. The old token stays mutably borrowed for as long as the new one lives,
+//! so only the newest token can be used to acquire further locks.
+//!
+//! The first token is created with the unsafe [`LockToken::unlocked`], at a point where the
+//! current thread is known to hold no locks, e.g. at the start of `main` or of a thread. This is
+//! the only assumption the compile time checks rely on.
+//!
+//! The locks in this module wrap [`crate::Mutex`] and [`crate::RwLock`], using [`Level::LEVEL`]
+//! as their level. They are still checked at runtime and take part in the same hierarchy as the
+//! other locks, so a code base can be migrated one lock at a time. Tokens are zero sized, so the
+//! compile time checks cost nothing at runtime.
+//!
+//! ```
+//! use lock_hierarchy::{lock_after, typed::{Level, LockToken, Mutex}};
+//!
+//! enum Accounts {}
+//! impl Level for Accounts {
+//!     const LEVEL: u32 = 1;
+//! }
+//!
+//! enum Audit {}
+//! impl Level for Audit {
+//!     const LEVEL: u32 = 0;
+//! }
+//!
+//! lock_after!(Accounts => Audit);
+//!
+//! let accounts = Mutex::<_, Accounts>::new(vec![10, 20]);
+//! let audit = Mutex::<_, Audit>::new(Vec::new());
+//!
+//! // SAFETY: No lock has been acquired yet
+//! let mut token = unsafe { LockToken::unlocked() };
+//! let (balances, mut token) = accounts.lock(&mut token).unwrap();
+//! let (mut log, _) = audit.lock(&mut token).unwrap();
+//! log.push(balances.iter().sum::<i32>());
+//! ```
+//!
+//! Acquiring the locks the other way around does not compile:
+//!
+//! ```compile_fail
+//! # use lock_hierarchy::{lock_after, typed::{Level, LockToken, Mutex}};
+//! # enum Accounts {}
+//! # impl Level for Accounts {
+//! #     const LEVEL: u32 = 1;
+//! # }
+//! # enum Audit {}
+//! # impl Level for Audit {
+//! #     const LEVEL: u32 = 0;
+//! # }
+//! # lock_after!(Accounts => Audit);
+//! # let accounts = Mutex::<_, Accounts>::new(());
+//! # let audit = Mutex::<_, Audit>::new(());
+//! let mut token = unsafe { LockToken::unlocked() };
+//! let (_log, mut token) = audit.lock(&mut token).unwrap();
+//! // Audit does not implement LockAfter<Accounts>
+//! let (_balances, _) = accounts.lock(&mut token).unwrap();
+//! ```
+
+use std::{
+    fmt::{Debug, Formatter},
+    marker::PhantomData,
+    sync::{LockResult, TryLockResult},
+};
+
+use crate::{map_guard, map_try_guard, MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+/// A level in a hierarchy checked at compile time. Usually implemented by an uninhabited type,
+/// e.g. `enum Database {}`.
+pub trait Level {
+    /// Level used for the runtime checks. Must be lower than the levels of every level this one is
+    /// [`LockAfter`]. [`lock_after!`](crate::lock_after) asserts this.
+    const LEVEL: u32;
+}
+
+/// Implemented by levels which may be acquired while holding a lock with level `A`.
+pub trait LockAfter<A: Level>: Level {}
+
+/// The level of a [`LockToken`] proving that no lock is held.
+#[derive(Debug)]
+pub enum Unlocked {}
+
+impl Level for Unlocked {
+    const LEVEL: u32 = u32::MAX;
+}
+
+impl<L: Level> LockAfter<Unlocked> for L {}
+
+/// Proves that the most recently acquired lock of the current thread has level `L`. Only locks
+/// which are [`LockAfter<L>`] can be acquired with it.
+pub struct LockToken<'a, L> {
+    /// Borrows the token it has been created from.
+    _previous: PhantomData<&'a mut ()>,
+    _level: PhantomData<fn() -> L>,
+    /// Tokens are bound to the locks of the current thread, so they must not be sent to others.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl LockToken<'static, Unlocked> {
+    /// Token to start acquiring locks with, e.g. at the entry point of a thread or task.
+    ///
+    /// # Safety
+    ///
+    /// The current thread must not hold any lock for as long as the token or any token derived
+    /// from it lives. Otherwise the compile time checks are circumvented. Acquisitions made with
+    /// such a token are still checked at runtime in debug builds, and breaking this contract can
+    /// cause deadlocks, but no undefined behavior.
+    pub unsafe fn unlocked() -> Self {
+        LockToken::new()
+    }
+}
+
+impl<L> LockToken<'_, L> {
+    fn new() -> Self {
+        LockToken {
+            _previous: PhantomData,
+            _level: PhantomData,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<L> Debug for LockToken<'_, L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LockToken<{}>", std::any::type_name::<L>())
+    }
+}
+
+/// Declares a chain of [`Level`]s, each of which may be acquired while holding any of the levels
+/// before it. Fails to compile if their [`Level::LEVEL`]s are not descending.
+///
+/// ```
+/// use lock_hierarchy::{lock_after, typed::Level};
+///
+/// enum Database {}
+/// impl Level for Database {
+///     const LEVEL: u32 = 2;
+/// }
+/// enum Cache {}
+/// impl Level for Cache {
+///     const LEVEL: u32 = 1;
+/// }
+/// enum Metrics {}
+/// impl Level for Metrics {
+///     const LEVEL: u32 = 0;
+/// }
+///
+/// lock_after!(Database => Cache => Metrics);
+/// ```
+#[macro_export]
+macro_rules! lock_after {
+    ($first:ty $(=> $rest:ty)+) => {
+        $(
+            impl $crate::typed::LockAfter<$first> for $rest {}
+            const _: () = assert!(
+                <$rest as $crate::typed::Level>::LEVEL < <$first as $crate::typed::Level>::LEVEL,
+                "Levels must be descending"
+            );
+        )+
+        $crate::lock_after!($($rest)=>+);
+    };
+    ($last:ty) => {};
+}
+
+/// A [`crate::Mutex`] whose level is the type `L`.
+///
+/// See the [module level documentation](self).
+pub struct Mutex<T, L> {
+    inner: crate::Mutex<T>,
+    _level: PhantomData<fn() -> L>,
+}
+
+impl<T, L: Level> Mutex<T, L> {
+    /// Creates a mutex with level [`L::LEVEL`](Level::LEVEL).
+    pub fn new(t: T) -> Self {
+        Mutex {
+            inner: crate::Mutex::with_level(t, L::LEVEL),
+            _level: PhantomData,
+        }
+    }
+
+    /// Like [`Self::new`], but also names the mutex. See [`crate::Mutex::with_name_and_level`].
+    pub fn with_name(t: T, name: &'static str) -> Self {
+        Mutex {
+            inner: crate::Mutex::with_name_and_level(t, name, L::LEVEL),
+            _level: PhantomData,
+        }
+    }
+
+    /// Acquires the mutex while holding a lock with level `A`. Returns the guard together with a
+    /// token for acquiring locks with lower levels.
+    #[track_caller]
+    pub fn lock<'a, A: Level>(
+        &'a self,
+        _token: &'a mut LockToken<'_, A>,
+    ) -> LockResult<(MutexGuard<'a, T>, LockToken<'a, L>)>
+    where
+        L: LockAfter<A>,
+    {
+        map_guard(self.inner.lock(), |guard| (guard, LockToken::new()))
+    }
+
+    /// See [`crate::Mutex::try_lock`]. A non blocking attempt can not deadlock, yet the token is
+    /// still required, since the returned token must not allow for acquiring higher levels.
+    #[track_caller]
+    pub fn try_lock<'a, A: Level>(
+        &'a self,
+        _token: &'a mut LockToken<'_, A>,
+    ) -> TryLockResult<(MutexGuard<'a, T>, LockToken<'a, L>)>
+    where
+        L: LockAfter<A>,
+    {
+        map_try_guard(self.inner.try_lock(), |guard| (guard, LockToken::new()))
+    }
+
+    /// The underlying mutex, which is only checked at runtime. Allows for acquiring it from code
+    /// which has not been migrated to tokens yet.
+    pub fn untyped(&self) -> &crate::Mutex<T> {
+        &self.inner
+    }
+
+    /// See [`crate::Mutex::get_mut`]
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+
+    /// See [`crate::Mutex::into_inner`]
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: Debug, L> Debug for Mutex<T, L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mutex")
+            .field("inner", &self.inner)
+            .field("level", &std::any::type_name::<L>())
+            .finish()
+    }
+}
+
+impl<T: Default, L: Level> Default for Mutex<T, L> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+/// A [`crate::RwLock`] whose level is the type `L`.
+///
+/// See the [module level documentation](self).
+pub struct RwLock<T, L> {
+    inner: crate::RwLock<T>,
+    _level: PhantomData<fn() -> L>,
+}
+
+impl<T, L: Level> RwLock<T, L> {
+    /// Creates a lock with level [`L::LEVEL`](Level::LEVEL).
+    pub fn new(t: T) -> Self {
+        RwLock {
+            inner: crate::RwLock::with_level(t, L::LEVEL),
+            _level: PhantomData,
+        }
+    }
+
+    /// Like [`Self::new`], but also names the lock. See [`crate::RwLock::with_name_and_level`].
+    pub fn with_name(t: T, name: &'static str) -> Self {
+        RwLock {
+            inner: crate::RwLock::with_name_and_level(t, name, L::LEVEL),
+            _level: PhantomData,
+        }
+    }
+
+    /// Acquires shared read access while holding a lock with level `A`. Returns the guard
+    /// together with a token for acquiring locks with lower levels.
+    #[track_caller]
+    pub fn read<'a, A: Level>(
+        &'a self,
+        _token: &'a mut LockToken<'_, A>,
+    ) -> LockResult<(RwLockReadGuard<'a, T>, LockToken<'a, L>)>
+    where
+        L: LockAfter<A>,
+    {
+        map_guard(self.inner.read(), |guard| (guard, LockToken::new()))
+    }
+
+    /// Acquires exclusive write access while holding a lock with level `A`. Returns the guard
+    /// together with a token for acquiring locks with lower levels.
+    #[track_caller]
+    pub fn write<'a, A: Level>(
+        &'a self,
+        _token: &'a mut LockToken<'_, A>,
+    ) -> LockResult<(RwLockWriteGuard<'a, T>, LockToken<'a, L>)>
+    where
+        L: LockAfter<A>,
+    {
+        map_guard(self.inner.write(), |guard| (guard, LockToken::new()))
+    }
+
+    /// The underlying lock, which is only checked at runtime. Allows for acquiring it from code
+    /// which has not been migrated to tokens yet.
+    pub fn untyped(&self) -> &crate::RwLock<T> {
+        &self.inner
+    }
+
+    /// See [`crate::RwLock::get_mut`]
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+
+    /// See [`crate::RwLock::into_inner`]
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: Debug, L> Debug for RwLock<T, L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RwLock")
+            .field("inner", &self.inner)
+            .field("level", &std::any::type_name::<L>())
+            .finish()
+    }
+}
+
+impl<T: Default, L: Level> Default for RwLock<T, L> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(lock_check)]
+    use crate::violation::catch_violation;
+
+    enum High {}
+    impl Level for High {
+        const LEVEL: u32 = 2;
+    }
+
+    enum Middle {}
+    impl Level for Middle {
+        const LEVEL: u32 = 1;
+    }
+
+    enum Low {}
+    impl Level for Low {
+        const LEVEL: u32 = 0;
+    }
+
+    lock_after!(High => Middle => Low);
+
+    #[test]
+    fn acquire_in_order() {
+        let high = Mutex::<_, High>::new(1);
+        let middle = RwLock::<_, Middle>::new(2);
+        let low = Mutex::<_, Low>::new(3);
+
+        // SAFETY: The test holds no locks yet
+        let mut token = unsafe { LockToken::unlocked() };
+        let (high, mut token) = high.lock(&mut token).unwrap();
+        let (middle, mut token) = middle.read(&mut token).unwrap();
+        let (low, _) = low.lock(&mut token).unwrap();
+        assert_eq!(6, *high + *middle + *low);
+    }
+
+    #[test]
+    fn skip_levels() {
+        let high = Mutex::<_, High>::new(());
+        let low = Mutex::<_, Low>::new(());
+
+        // SAFETY: The test holds no locks yet
+        let mut token = unsafe { LockToken::unlocked() };
+        let (_high, mut token) = high.lock(&mut token).unwrap();
+        // Fine, the chain is transitive
+        let _low = low.lock(&mut token).unwrap();
+    }
+
+    #[test]
+    fn runtime_level_is_taken_from_type() {
+        let middle = RwLock::<_, Middle>::new(());
+        let runtime = crate::Mutex::with_level((), 0);
+
+        // SAFETY: The test holds no locks yet
+        let mut token = unsafe { LockToken::unlocked() };
+        let (_middle, _) = middle.write(&mut token).unwrap();
+        // Fine: 0 is lower level than 1
+        let _runtime = runtime.lock().unwrap();
+    }
+
+    #[test]
+    #[cfg(lock_check)]
+    fn untyped_acquisitions_are_checked_at_runtime() {
+        assert_eq!(
+            catch_violation(|| {
+                let low = Mutex::<_, Low>::new(());
+                let high = Mutex::<_, High>::new(());
+                let _low = low.untyped().lock().unwrap();
+                // Must panic, the runtime check still applies
+                // SAFETY: Not upheld on purpose, `low` is held
+                let mut token = unsafe { LockToken::unlocked() };
+                let _high = high.lock(&mut token);
+            }),
+            (Some(2), vec![0])
+        );
+    }
+}
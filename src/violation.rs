@@ -0,0 +1,431 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, PoisonError,
+    },
+};
+
+use crate::{AnyLevel, LockInfo, ObservedOrder};
+
+/// A detected violation of the lock hierarchy.
+///
+/// Passed to the [`ViolationHandler`]. The default handler panics with it as payload.
+#[derive(Debug, Clone)]
+pub struct HierarchyViolation {
+    kind: ViolationKind,
+    /// Boxed to keep `Result`s with violations small.
+    attempted: Option<Box<LockInfo>>,
+    held: Vec<LockInfo>,
+    /// Boxed, since it is rarely present.
+    blocking_section: Option<Box<LockInfo>>,
+    /// Boxed slice, since it is rarely present. Keeps `Result`s with violations small.
+    cycle: Box<[ObservedOrder]>,
+}
+
+/// The kind of a [`HierarchyViolation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ViolationKind {
+    /// A lock has been acquired, while a lock with the same or a lower level has been held.
+    LockOrder,
+    /// A future has been suspended, while holding locks acquired during that poll. See
+    /// [`check_no_locks_across_await`](crate::future::check_no_locks_across_await).
+    HeldAcrossAwait,
+    /// A lock has been held in a [`BlockingSection`](crate::BlockingSection) or by a thread
+    /// calling [`assert_no_locks_held`](crate::assert_no_locks_held).
+    HeldWhileBlocking,
+    /// A lock has been acquired in an order contradicting the order observed before. Only
+    /// detected with [`CheckMode::Graph`](crate::CheckMode::Graph) or
+    /// [`CheckMode::Both`](crate::CheckMode::Both).
+    LockOrderCycle,
+}
+
+impl HierarchyViolation {
+    #[cfg(lock_check)]
+    pub(crate) fn lock_order(attempted: LockInfo, held: Vec<LockInfo>) -> Self {
+        Self {
+            kind: ViolationKind::LockOrder,
+            attempted: Some(Box::new(attempted)),
+            held,
+            blocking_section: None,
+            cycle: Box::new([]),
+        }
+    }
+
+    #[cfg(lock_check)]
+    pub(crate) fn held_across_await(held: Vec<LockInfo>) -> Self {
+        Self {
+            kind: ViolationKind::HeldAcrossAwait,
+            attempted: None,
+            held,
+            blocking_section: None,
+            cycle: Box::new([]),
+        }
+    }
+
+    #[cfg(lock_check)]
+    pub(crate) fn held_while_blocking(
+        blocking_section: LockInfo,
+        attempted: Option<LockInfo>,
+        held: Vec<LockInfo>,
+    ) -> Self {
+        Self {
+            kind: ViolationKind::HeldWhileBlocking,
+            attempted: attempted.map(Box::new),
+            held,
+            blocking_section: Some(Box::new(blocking_section)),
+            cycle: Box::new([]),
+        }
+    }
+
+    #[cfg(lock_check)]
+    pub(crate) fn lock_order_cycle(
+        attempted: LockInfo,
+        held: Vec<LockInfo>,
+        cycle: Vec<ObservedOrder>,
+    ) -> Self {
+        Self {
+            kind: ViolationKind::LockOrderCycle,
+            attempted: Some(Box::new(attempted)),
+            held,
+            blocking_section: None,
+            cycle: cycle.into_boxed_slice(),
+        }
+    }
+
+    /// What kind of violation this is. Decides which of the other accessors return anything.
+    pub fn kind(&self) -> ViolationKind {
+        self.kind
+    }
+
+    /// The lock which has been tried to acquire, if the violation has been caused by an
+    /// acquisition.
+    pub fn attempted(&self) -> Option<&LockInfo> {
+        self.attempted.as_deref()
+    }
+
+    /// For [`ViolationKind::LockOrder`] all locks held by the thread at the time of the violation,
+    /// in the order they have been acquired. For [`ViolationKind::HeldAcrossAwait`] the locks held
+    /// across the await point.
+    pub fn held(&self) -> &[LockInfo] {
+        &self.held
+    }
+
+    /// For [`ViolationKind::HeldWhileBlocking`] the blocking section, or the call to
+    /// [`assert_no_locks_held`](crate::assert_no_locks_held). Its level is the threshold, i.e.
+    /// only locks with lower levels may be held. If no locks may be held at all, its level is
+    /// lower than any other level and displayed as `bottom`.
+    pub fn blocking_section(&self) -> Option<&LockInfo> {
+        self.blocking_section.as_deref()
+    }
+
+    /// For [`ViolationKind::LockOrderCycle`] the contradicting orders. The first one is the
+    /// attempted acquisition, the others have been observed before.
+    pub fn cycle(&self) -> &[ObservedOrder] {
+        &self.cycle
+    }
+}
+
+impl HierarchyViolation {
+    /// Whether the held lock is part of the violation, rather than just held at the time.
+    fn is_conflicting(&self, held: &LockInfo) -> bool {
+        match (self.kind, self.attempted(), &self.blocking_section) {
+            (ViolationKind::LockOrder, Some(attempted), _) => {
+                !attempted.level().is_lower_than(held.level())
+            }
+            (ViolationKind::HeldWhileBlocking, None, Some(section)) => {
+                !held.level().is_lower_than(section.level())
+            }
+            (ViolationKind::HeldWhileBlocking, _, _) => false,
+            (ViolationKind::LockOrderCycle, _, _) => self
+                .cycle
+                .first()
+                .is_some_and(|order| order.held().instance() == held.instance()),
+            _ => true,
+        }
+    }
+}
+
+impl Display for HierarchyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.kind, self.attempted()) {
+            (ViolationKind::LockOrder, Some(attempted)) => {
+                let lowest = self
+                    .held
+                    .iter()
+                    .filter(|held| self.is_conflicting(held))
+                    .min_by(|a, b| {
+                        a.level()
+                            .partial_cmp(b.level())
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    });
+                f.write_str("Tried to acquire lock")?;
+                if let Some(name) = attempted.name() {
+                    write!(f, " {name:?}")?;
+                }
+                write!(f, " with level {} while ", attempted.level())?;
+                match lowest.and_then(LockInfo::name) {
+                    Some(name) => write!(f, "lock {name:?}")?,
+                    None => f.write_str("a lock")?,
+                }
+                write!(
+                    f,
+                    " with level {} is acquired. This is a violation of lock hierarchies which \
+                    could lead to deadlocks.",
+                    lowest.map_or(&AnyLevel::from_u32(0), LockInfo::level)
+                )?;
+            }
+            (ViolationKind::LockOrderCycle, Some(attempted)) => write!(
+                f,
+                "Tried to acquire lock with level {} in an order contradicting the order locks \
+                have been acquired in before. This could lead to deadlocks.",
+                attempted.level()
+            )?,
+            (ViolationKind::HeldWhileBlocking, attempted) => {
+                let threshold = self
+                    .blocking_section
+                    .as_ref()
+                    .map(|section| section.level())
+                    .filter(|&threshold| *threshold != AnyLevel::BOTTOM);
+                match attempted {
+                    Some(attempted) => write!(
+                        f,
+                        "Tried to acquire lock with level {} within a blocking section.",
+                        attempted.level()
+                    )?,
+                    None => {
+                        let levels: Vec<_> = self.held.iter().map(LockInfo::level).collect();
+                        write!(
+                            f,
+                            "Entered a blocking section while holding locks with levels {levels:?}."
+                        )?
+                    }
+                }
+                match threshold {
+                    None => f.write_str(" No locks may be held while blocking.")?,
+                    Some(threshold) => write!(
+                        f,
+                        " Only locks with levels lower than {threshold} may be held while blocking."
+                    )?,
+                }
+            }
+            _ => {
+                let levels: Vec<_> = self.held.iter().map(LockInfo::level).collect();
+                write!(
+                    f,
+                    "Future is suspended while holding locks with levels {levels:?}, which have \
+                    been acquired while polling it. Holding locks across an await point could \
+                    lead to deadlocks."
+                )?;
+            }
+        }
+        if let Some(section) = &self.blocking_section {
+            write!(f, "\n  blocking section: {section}")?;
+            write_backtrace(f, section)?;
+        }
+        if let Some(attempted) = &self.attempted {
+            write!(f, "\n  attempted: {attempted}")?;
+            write_backtrace(f, attempted)?;
+        }
+        for order in &self.cycle {
+            write!(f, "\n  order: {order}")?;
+        }
+        for held in &self.held {
+            write!(f, "\n  held: {held}")?;
+            // Only the backtraces of the conflicting locks are of interest
+            if self.is_conflicting(held) {
+                write_backtrace(f, held)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for HierarchyViolation {}
+
+/// Writes the backtrace of the acquisition, if it has been captured.
+fn write_backtrace(f: &mut Formatter<'_>, info: &LockInfo) -> std::fmt::Result {
+    match info.backtrace() {
+        Some(backtrace) => write!(f, "\n  backtrace:\n{}", backtrace.to_string().trim_end()),
+        None => Ok(()),
+    }
+}
+
+/// Error returned by the `checked_*` acquisitions, e.g. [`Mutex::checked_lock`](crate::Mutex::checked_lock).
+pub enum CheckedLockError<T> {
+    /// The lock has been acquired, but it is poisoned. See [`PoisonError`].
+    Poisoned(PoisonError<T>),
+    /// Acquiring the lock would have violated the lock hierarchy. The lock has not been touched.
+    Violation(HierarchyViolation),
+}
+
+/// Result of the `checked_*` acquisitions, e.g. [`Mutex::checked_lock`](crate::Mutex::checked_lock).
+pub type CheckedLockResult<G> = Result<G, CheckedLockError<G>>;
+
+impl<T> From<PoisonError<T>> for CheckedLockError<T> {
+    fn from(err: PoisonError<T>) -> Self {
+        CheckedLockError::Poisoned(err)
+    }
+}
+
+impl<T> From<HierarchyViolation> for CheckedLockError<T> {
+    fn from(violation: HierarchyViolation) -> Self {
+        CheckedLockError::Violation(violation)
+    }
+}
+
+impl<T> Debug for CheckedLockError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckedLockError::Poisoned(err) => Debug::fmt(err, f),
+            CheckedLockError::Violation(violation) => Debug::fmt(violation, f),
+        }
+    }
+}
+
+impl<T> Display for CheckedLockError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckedLockError::Poisoned(err) => Display::fmt(err, f),
+            CheckedLockError::Violation(violation) => Display::fmt(violation, f),
+        }
+    }
+}
+
+impl<T> Error for CheckedLockError<T> {}
+
+/// Decides what happens once a violation of the lock hierarchy is detected. Set it with
+/// [`set_violation_handler`].
+///
+/// Every policy besides [`ViolationHandler::Panic`] and [`ViolationHandler::Abort`] continues
+/// after the violation, i.e. the lock is acquired anyway. This may deadlock, just like it would
+/// without any checks.
+#[derive(Clone)]
+pub enum ViolationHandler {
+    /// Panic with the [`HierarchyViolation`] as payload. This is the default.
+    ///
+    /// The panic hook does not know how to print this payload, so the violation is printed to
+    /// stderr right before panicking, also if the panic is caught later on.
+    Panic,
+    /// Print the violation to stderr and continue.
+    Log,
+    /// Only count the violation and continue. See [`violation_count`].
+    Count,
+    /// Store the violation and continue. See [`take_violations`].
+    Collect,
+    /// Print the violation to stderr and abort the process.
+    Abort,
+    /// Call a user supplied function with the violation. Continues if the function returns. The
+    /// function may acquire locks or replace the handler.
+    Custom(Arc<dyn Fn(&HierarchyViolation) + Send + Sync>),
+}
+
+impl ViolationHandler {
+    /// Shorthand for [`ViolationHandler::Custom`].
+    pub fn custom(f: impl Fn(&HierarchyViolation) + Send + Sync + 'static) -> Self {
+        ViolationHandler::Custom(Arc::new(f))
+    }
+}
+
+impl Debug for ViolationHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ViolationHandler::Panic => f.write_str("Panic"),
+            ViolationHandler::Log => f.write_str("Log"),
+            ViolationHandler::Count => f.write_str("Count"),
+            ViolationHandler::Collect => f.write_str("Collect"),
+            ViolationHandler::Abort => f.write_str("Abort"),
+            ViolationHandler::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+static HANDLER: std::sync::RwLock<ViolationHandler> =
+    std::sync::RwLock::new(ViolationHandler::Panic);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static VIOLATIONS: std::sync::Mutex<Vec<HierarchyViolation>> = std::sync::Mutex::new(Vec::new());
+
+/// Sets the handler for all violations detected from now on, in any thread, and returns the
+/// previous one.
+///
+/// ```
+/// use lock_hierarchy::{set_violation_handler, take_violations, Mutex, ViolationHandler};
+///
+/// let previous = set_violation_handler(ViolationHandler::Collect);
+/// let mutex_a = Mutex::new(());
+/// let mutex_b = Mutex::new(());
+/// let _guard_a = mutex_a.lock().unwrap();
+/// // Lock hierarchy violation, collected instead of panicking
+/// let _guard_b = mutex_b.lock().unwrap();
+/// # #[cfg(lock_check)]
+/// assert_eq!(1, take_violations().len());
+/// set_violation_handler(previous);
+/// ```
+pub fn set_violation_handler(handler: ViolationHandler) -> ViolationHandler {
+    let mut current = HANDLER.write().unwrap_or_else(PoisonError::into_inner);
+    std::mem::replace(&mut *current, handler)
+}
+
+/// Number of violations detected by this process so far, independent of the handler.
+pub fn violation_count() -> usize {
+    COUNT.load(Ordering::Relaxed)
+}
+
+/// Removes and returns all violations collected by [`ViolationHandler::Collect`] so far.
+pub fn take_violations() -> Vec<HierarchyViolation> {
+    std::mem::take(&mut *VIOLATIONS.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Hands a violation to the current handler.
+#[cfg(lock_check)]
+pub(crate) fn report(violation: HierarchyViolation) {
+    COUNT.fetch_add(1, Ordering::Relaxed);
+    // Not held while handling the violation, so a custom handler may replace the handler, and a
+    // panic does not poison it
+    let handler = HANDLER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    match handler {
+        ViolationHandler::Panic => {
+            // The default panic hook does not know how to print a custom payload
+            eprintln!("{violation}");
+            std::panic::panic_any(violation)
+        }
+        ViolationHandler::Log => eprintln!("{violation}"),
+        ViolationHandler::Count => (),
+        ViolationHandler::Collect => VIOLATIONS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(violation),
+        ViolationHandler::Abort => {
+            eprintln!("{violation}");
+            std::process::abort()
+        }
+        ViolationHandler::Custom(f) => f(&violation),
+    }
+}
+
+/// Runs `f`, which must cause a violation, and returns the attempted level and the levels held at
+/// the time of the violation.
+#[cfg(all(test, lock_check))]
+pub(crate) fn catch_violation(f: impl FnOnce()) -> (Option<u32>, Vec<u32>) {
+    let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+        .expect_err("Expected a violation of the lock hierarchy");
+    let violation = payload
+        .downcast::<HierarchyViolation>()
+        .expect("Expected a HierarchyViolation as panic payload");
+    let level = |info: &LockInfo| {
+        let level = info
+            .level()
+            .as_integer()
+            .expect("Expected an integer level");
+        u32::try_from(level).expect("Expected a u32 level")
+    };
+    (
+        violation.attempted().map(level),
+        violation.held().iter().map(level).collect(),
+    )
+}
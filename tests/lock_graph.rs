@@ -0,0 +1,81 @@
+//! The check mode is global, so these tests live in their own binary and run one at a time.
+#![cfg(lock_check)]
+
+use std::{
+    sync::{Arc, Mutex as StdMutex, MutexGuard as StdMutexGuard, PoisonError},
+    thread,
+};
+
+use lock_hierarchy::{set_check_mode, CheckMode, CheckedLockError, Mutex, ViolationKind};
+
+/// Serializes the tests in this file, since they all change the global mode.
+static SERIAL: StdMutex<()> = StdMutex::new(());
+
+/// Sets the mode for the duration of a test.
+struct Mode(#[allow(dead_code)] StdMutexGuard<'static, ()>, CheckMode);
+
+impl Mode {
+    fn set(mode: CheckMode) -> Self {
+        let serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+        Mode(serial, set_check_mode(mode))
+    }
+}
+
+impl Drop for Mode {
+    fn drop(&mut self) {
+        set_check_mode(self.1);
+    }
+}
+
+#[test]
+fn opposite_order_on_other_thread_is_reported() {
+    let _mode = Mode::set(CheckMode::Graph);
+    let mutex_a = Arc::new(Mutex::new(()));
+    let mutex_b = Arc::new(Mutex::new(()));
+
+    // Fine, levels are not checked
+    thread::scope(|s| {
+        s.spawn(|| {
+            let _guard_a = mutex_a.lock().unwrap();
+            let _guard_b = mutex_b.lock().unwrap();
+        });
+    });
+
+    let _guard_b = mutex_b.lock().unwrap();
+    let Err(CheckedLockError::Violation(violation)) = mutex_a.checked_lock() else {
+        panic!("Expected a violation");
+    };
+    assert_eq!(ViolationKind::LockOrderCycle, violation.kind());
+    let cycle = violation.cycle();
+    assert_eq!(2, cycle.len());
+    assert_eq!(cycle[0].held().instance(), cycle[1].acquired().instance());
+    assert_eq!(cycle[0].acquired().instance(), cycle[1].held().instance());
+}
+
+#[test]
+fn dropped_locks_are_forgotten() {
+    let _mode = Mode::set(CheckMode::Graph);
+    let mutex_a = Mutex::new(());
+    {
+        let mutex_b = Mutex::new(());
+        let _guard_a = mutex_a.lock().unwrap();
+        let _guard_b = mutex_b.lock().unwrap();
+    }
+    // Fine, the order of mutex_b does not matter anymore
+    let mutex_c = Mutex::new(());
+    let _guard_c = mutex_c.lock().unwrap();
+    let _guard_a = mutex_a.checked_lock().unwrap();
+}
+
+#[test]
+fn both_modes_check_levels() {
+    let _mode = Mode::set(CheckMode::Both);
+    let mutex_a = Mutex::with_level((), 1);
+    let mutex_b = Mutex::with_level((), 1);
+
+    let _guard_a = mutex_a.lock().unwrap();
+    let Err(CheckedLockError::Violation(violation)) = mutex_b.checked_lock() else {
+        panic!("Expected a violation");
+    };
+    assert_eq!(ViolationKind::LockOrder, violation.kind());
+}
@@ -0,0 +1,117 @@
+//! The violation handler is global, so these tests live in their own binary and run one at a time.
+#![cfg(lock_check)]
+
+use std::sync::{Arc, Mutex as StdMutex, PoisonError};
+
+use lock_hierarchy::{
+    set_violation_handler, take_violations, violation_count, HierarchyViolation, Mutex,
+    ViolationHandler, ViolationKind,
+};
+
+/// Serializes the tests in this file, since they all change the global handler.
+static SERIAL: StdMutex<()> = StdMutex::new(());
+
+/// Acquires two locks with level 0, which is a violation.
+fn violate() {
+    let mutex_a = Mutex::new(());
+    let mutex_b = Mutex::new(());
+    let _guard_a = mutex_a.lock().unwrap();
+    let _guard_b = mutex_b.lock().unwrap();
+}
+
+#[test]
+fn collect() {
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    let previous = set_violation_handler(ViolationHandler::Collect);
+    take_violations();
+
+    violate();
+    violate();
+
+    set_violation_handler(previous);
+    let violations = take_violations();
+    assert_eq!(2, violations.len());
+    for violation in violations {
+        assert_eq!(ViolationKind::LockOrder, violation.kind());
+        assert_eq!(Some(0), violation.attempted().unwrap().level().as_integer());
+        assert_eq!(1, violation.held().len());
+    }
+}
+
+#[test]
+fn count() {
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    let previous = set_violation_handler(ViolationHandler::Count);
+    let before = violation_count();
+
+    violate();
+
+    set_violation_handler(previous);
+    assert_eq!(before + 1, violation_count());
+}
+
+#[test]
+fn custom() {
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    let descriptions = Arc::new(StdMutex::new(Vec::new()));
+    let previous = set_violation_handler(ViolationHandler::custom({
+        let descriptions = descriptions.clone();
+        move |violation: &HierarchyViolation| {
+            descriptions.lock().unwrap().push(violation.to_string())
+        }
+    }));
+
+    violate();
+
+    set_violation_handler(previous);
+    assert_eq!(1, descriptions.lock().unwrap().len());
+}
+
+#[test]
+fn custom_handler_may_acquire_locks() {
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    let log = Arc::new(Mutex::with_level(Vec::new(), 0));
+    let previous = set_violation_handler(ViolationHandler::custom({
+        let log = log.clone();
+        move |violation: &HierarchyViolation| log.try_lock().unwrap().push(violation.clone())
+    }));
+
+    violate();
+
+    set_violation_handler(previous);
+    assert_eq!(1, log.lock().unwrap().len());
+}
+
+#[test]
+fn custom_handler_may_replace_itself() {
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    let calls = Arc::new(StdMutex::new(0));
+    let previous = set_violation_handler(ViolationHandler::custom({
+        let calls = calls.clone();
+        move |_: &HierarchyViolation| {
+            *calls.lock().unwrap() += 1;
+            set_violation_handler(ViolationHandler::Count);
+        }
+    }));
+    let before = violation_count();
+
+    violate();
+    violate();
+
+    set_violation_handler(previous);
+    assert_eq!(1, *calls.lock().unwrap());
+    assert_eq!(before + 2, violation_count());
+}
+
+#[test]
+fn panic_by_default() {
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    let violation = *std::panic::catch_unwind(violate)
+        .unwrap_err()
+        .downcast::<HierarchyViolation>()
+        .unwrap();
+    assert!(violation.to_string().starts_with(
+        "Tried to acquire lock with level 0 while a lock with level 0 is acquired. This is a \
+        violation of lock hierarchies which could lead to deadlocks."
+    ));
+}